@@ -0,0 +1,187 @@
+//! Pinning client and server threads to CPUs, so that a client and server on one machine don't
+//! compete for the same cores or migrate between them mid-run.
+//!
+//! A binary calls [`pin`] once at startup, which pins its main thread and so every thread it
+//! spawns afterwards. Threads that do a run's work then call [`place_thread`], which moves each
+//! to a CPU of its own under [`Placement::Spread`]. Threads that own a share of the work, like the
+//! server's SO_REUSEPORT listeners, pick their CPU with [`pin_thread_to`] instead.
+
+use nix::{
+    sched::{sched_getaffinity, sched_setaffinity, CpuSet},
+    unistd::Pid,
+};
+use std::{
+    num::ParseIntError,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+};
+
+/// A list of CPU numbers.
+///
+/// Implements [`FromStr`](std::str::FromStr). String format is a comma-separated list of CPUs and
+/// inclusive ranges, as in `taskset -c`, e.g. `0-3,6`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuList(pub Vec<usize>);
+
+impl std::str::FromStr for CpuList {
+    type Err = CpuListParseErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cpus = Vec::new();
+        for part in s.split(',') {
+            match part.trim().split_once('-') {
+                Some((start, end)) => {
+                    let (start, end): (usize, usize) = (start.parse()?, end.parse()?);
+                    if start > end {
+                        return Err(CpuListParseErr::UnknownFmt(s.to_owned()));
+                    }
+                    cpus.extend(start..=end);
+                }
+                None => cpus.push(part.trim().parse()?),
+            }
+        }
+        if let Some(&cpu) = cpus.iter().find(|&&cpu| cpu >= CpuSet::count()) {
+            return Err(CpuListParseErr::OutOfRange(cpu));
+        }
+        Ok(CpuList(cpus))
+    }
+}
+
+impl std::fmt::Display for CpuList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cpus: Vec<_> = self.0.iter().map(usize::to_string).collect();
+        write!(f, "{}", cpus.join(","))
+    }
+}
+
+/// Things that can go wrong when parsing a [`CpuList`].
+#[derive(Debug)]
+pub enum CpuListParseErr {
+    /// A range wasn't `start-end` with `start <= end`.
+    UnknownFmt(String),
+    /// A CPU number was beyond what a CPU set can hold.
+    OutOfRange(usize),
+    /// A CPU number wasn't a `usize`.
+    UsizeParse(ParseIntError),
+}
+
+impl From<ParseIntError> for CpuListParseErr {
+    fn from(value: ParseIntError) -> Self {
+        Self::UsizeParse(value)
+    }
+}
+
+impl std::fmt::Display for CpuListParseErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownFmt(s) => {
+                write!(f, "Unknown CPU list {}. Format is a comma-separated list of CPUs and ranges, e.g. 0-3,6.", s)
+            }
+            Self::OutOfRange(cpu) => write!(f, "CPU {} is out of range.", cpu),
+            Self::UsizeParse(n) => write!(f, "Could not parse CPU number {} as usize.", n),
+        }
+    }
+}
+
+impl std::error::Error for CpuListParseErr {}
+
+/// How threads are placed on the CPUs they're pinned to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Placement {
+    /// Every thread may run on any of the CPUs, and the scheduler balances them.
+    #[default]
+    Shared,
+    /// Each thread is pinned to one of the CPUs, taken round robin in the order started.
+    Spread,
+}
+
+impl std::fmt::Display for Placement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Shared => write!(f, "shared"),
+            Self::Spread => write!(f, "spread"),
+        }
+    }
+}
+
+struct Pinning {
+    cpus: CpuList,
+    placement: Placement,
+    /// Threads placed so far.
+    placed: AtomicUsize,
+}
+
+static PINNING: OnceLock<Pinning> = OnceLock::new();
+
+/// Pin the calling thread to `cpus`, and the run threads it later places according to
+/// `placement`. Only the first call has any effect.
+pub fn pin(cpus: CpuList, placement: Placement) -> nix::Result<()> {
+    set_affinity(&cpus.0)?;
+    let _ = PINNING.set(Pinning {
+        cpus,
+        placement,
+        placed: AtomicUsize::new(0),
+    });
+    Ok(())
+}
+
+/// Place the calling thread on its CPU, if threads are spread. Failures are reported but not
+/// fatal, since the thread stays within the CPUs pinned at startup.
+pub fn place_thread() {
+    let Some(pinning) = PINNING.get() else {
+        return;
+    };
+    if pinning.placement == Placement::Spread {
+        let i = pinning.placed.fetch_add(1, Ordering::Relaxed);
+        let cpu = pinning.cpus.0[i % pinning.cpus.0.len()];
+        if let Err(e) = set_affinity(&[cpu]) {
+            eprintln!("Failed to pin thread to CPU {}: {}", cpu, e);
+        }
+    }
+}
+
+/// Pin the calling thread to one CPU: the `i`th, round robin, of those pinned at startup or, if
+/// there were none, of those the process may run on. Returns the CPU.
+pub fn pin_thread_to(i: usize) -> nix::Result<usize> {
+    let cpus = match PINNING.get() {
+        Some(pinning) => pinning.cpus.0.clone(),
+        None => allowed_cpus()?,
+    };
+    let cpu = cpus[i % cpus.len()];
+    set_affinity(&[cpu])?;
+    Ok(cpu)
+}
+
+/// The CPUs the calling thread may run on.
+pub fn allowed_cpus() -> nix::Result<Vec<usize>> {
+    let set = sched_getaffinity(Pid::from_raw(0))?;
+    Ok((0..CpuSet::count())
+        .filter(|&cpu| set.is_set(cpu).unwrap_or(false))
+        .collect())
+}
+
+fn set_affinity(cpus: &[usize]) -> nix::Result<()> {
+    let mut set = CpuSet::new();
+    for &cpu in cpus {
+        set.set(cpu)?;
+    }
+    // Pid 0 is the calling thread.
+    sched_setaffinity(Pid::from_raw(0), &set)
+}
+
+#[cfg(test)]
+mod t {
+    use super::{CpuList, CpuListParseErr};
+
+    #[test]
+    fn parse_cpu_list() {
+        assert_eq!("0-3,6".parse::<CpuList>().expect("parse list"), CpuList(vec![0, 1, 2, 3, 6]));
+        assert_eq!("2".parse::<CpuList>().expect("parse single"), CpuList(vec![2]));
+        assert_eq!(CpuList(vec![0, 1, 6]).to_string(), "0,1,6");
+        assert!(matches!("3-1".parse::<CpuList>(), Err(CpuListParseErr::UnknownFmt(_))));
+        assert!(matches!("a-3".parse::<CpuList>(), Err(CpuListParseErr::UsizeParse(_))));
+        assert!(matches!("".parse::<CpuList>(), Err(CpuListParseErr::UsizeParse(_))));
+        assert!(matches!("100000".parse::<CpuList>(), Err(CpuListParseErr::OutOfRange(_))));
+    }
+}
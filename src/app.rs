@@ -1,9 +1,15 @@
 //! Application logic for the CS1675 network APIs project.
 
+use crate::kv::{self, KvWorkload};
 use serde::{Deserialize, Serialize};
 use std::{
-    num::{NonZeroU64, ParseIntError},
-    time::Duration,
+    fs::{File, OpenOptions},
+    io::Write,
+    num::{NonZeroU64, ParseFloatError, ParseIntError},
+    os::unix::fs::{FileExt, OpenOptionsExt},
+    path::PathBuf,
+    sync::OnceLock,
+    time::{Duration, Instant},
 };
 
 /// Describes a type and amount of busy-work to do.
@@ -12,16 +18,131 @@ use std::{
 /// - `immediate|imm`
 /// - `poisson:[amount]` (amount must be nonzero)
 /// - `[const|busytime|bt|busywork|bw]:[amount]`
+/// - `[busywork|bw]:[amount]us` (busy work calibrated to take `amount` microseconds)
+/// - `[exponential|exp]:[mean]` (mean must be nonzero)
+/// - `lognormal:[mean],[sigma]` (mean must be nonzero, sigma is a non-negative f64)
+/// - `pareto:[mean],[shape]` (mean must be nonzero, shape is an f64 greater than 1)
+/// - `bimodal:[short],[long],[p_long]` (p_long is the f64 probability of the long mode)
+/// - `chase:[working_set_kib],[steps]` (working set between 1 and [`MAX_WORKING_SET_KIB`])
+/// - `stream:[buffer_kib]` (buffer between 1 and [`MAX_STREAM_KIB`])
+/// - `sleep:[amount]`, `nanosleep:[amount]`
+/// - `fileread:[bytes]` or `fileread:[bytes],direct` (at most [`MAX_FILE_READ_BYTES`])
+/// - `get:[key]`, `set:[key],[value_len]` (value at most [`kv::MAX_VALUE_LEN`] bytes)
+/// - `[mutex|rwread|rwwrite]:[locks],[hold]` (between 1 and [`MAX_LOCKS`] locks)
+/// - `fanout:[fanout],[quorum],[leaf]` (1 <= quorum <= fanout)
+///
+/// All distribution parameters are in microseconds.
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Work {
     Immediate,
     Const(u64),
     Payload,
     Poisson(NonZeroU64),
+    Exponential(NonZeroU64),
+    LogNormal {
+        mean: NonZeroU64,
+        sigma: f64,
+    },
+    Pareto {
+        mean: NonZeroU64,
+        shape: f64,
+    },
+    Bimodal {
+        short: u64,
+        long: u64,
+        p_long: f64,
+    },
+    /// Follow `steps` dependent loads through a random cycle over a per-thread working set.
+    PointerChase {
+        working_set_kib: u64,
+        steps: u64,
+    },
+    /// Sum every word of a per-thread buffer once.
+    Stream {
+        buffer_kib: u64,
+    },
+    /// Block in [`std::thread::sleep`].
+    Sleep(u64),
+    /// Block in an absolute `clock_nanosleep` with minimal timer slack.
+    NanoSleep(u64),
+    /// Read `bytes` from a random offset of the scratch file, bypassing the page cache if
+    /// `direct`.
+    FileRead {
+        bytes: u64,
+        direct: bool,
+    },
+    /// Look up `key` in the server's [`kv::KvStore`].
+    Get(u64),
+    /// Store a `value_len`-byte value at `key` in the server's [`kv::KvStore`].
+    Set {
+        key: u64,
+        value_len: u64,
+    },
+    /// Take one of the first `locks` shared server locks at random and spin for `hold` while
+    /// holding it.
+    Lock {
+        kind: LockKind,
+        locks: u64,
+        hold: u64,
+    },
+    /// Send `fanout` sub-requests, each doing `leaf` of [`Self::Const`] work, to the server's
+    /// backends, and reply once `quorum` of them return.
+    FanOut {
+        fanout: u64,
+        quorum: u64,
+        leaf: u64,
+    },
 
     // what is this?
     BusyTimeConst(u64),
     BusyWorkConst(u64),
+    /// [`Self::BusyWorkConst`] scaled by the server's calibration to take this many microseconds.
+    BusyWorkUs(u64),
+}
+
+/// Iterations of [`fake_work`] per microsecond on this machine, measured by
+/// [`calibrate_busy_work`].
+static BUSY_WORK_ITERS_PER_US: OnceLock<f64> = OnceLock::new();
+
+/// `iters` iterations of Shenango's fake work loop.
+fn fake_work(iters: u64) {
+    // from shenango:
+    // https://github.com/shenango/shenango/blob/master/apps/synthetic/src/fakework.rs#L54
+    let k = 2350845.545;
+    for i in 0..iters {
+        std::hint::black_box(f64::sqrt(k * i as f64));
+    }
+}
+
+/// Measure how many [`Work::BusyWorkConst`] iterations this machine runs per microsecond.
+///
+/// Takes the median of several ~20ms trials. Only the first call measures; later calls return the
+/// same value.
+pub fn calibrate_busy_work() -> f64 {
+    *BUSY_WORK_ITERS_PER_US.get_or_init(|| {
+        const TRIAL: Duration = Duration::from_millis(20);
+
+        // Find an iteration count that takes about one trial.
+        let mut iters = 1000;
+        loop {
+            let start = Instant::now();
+            fake_work(iters);
+            if start.elapsed() >= TRIAL {
+                break;
+            }
+            iters *= 2;
+        }
+
+        let mut rates: Vec<f64> = (0..7)
+            .map(|_| {
+                let start = Instant::now();
+                fake_work(iters);
+                iters as f64 / start.elapsed().as_secs_f64() / 1e6
+            })
+            .collect();
+        rates.sort_by(f64::total_cmp);
+        rates[rates.len() / 2]
+    })
 }
 
 fn gen_poisson_duration(amt: NonZeroU64) -> Duration {
@@ -32,10 +153,342 @@ fn gen_poisson_duration(amt: NonZeroU64) -> Duration {
     Duration::from_micros(pois.sample(&mut rng) as u64)
 }
 
+fn gen_exponential_duration(mean: NonZeroU64) -> Duration {
+    use rand_distr::Distribution;
+
+    let exp = rand_distr::Exp::new(1.0 / mean.get() as f64).unwrap();
+    Duration::from_secs_f64(exp.sample(&mut rand::thread_rng()) / 1e6)
+}
+
+/// `us` microseconds, saturating rather than panicking on the far tail of a distribution.
+fn micros_f64(us: f64) -> Duration {
+    Duration::try_from_secs_f64(us / 1e6).unwrap_or(if us > 0.0 {
+        Duration::MAX
+    } else {
+        Duration::ZERO
+    })
+}
+
+// The distributions' parameters come from the client, so parameters the parser would reject are
+// clamped to the nearest it would accept rather than trusted.
+
+fn gen_lognormal_duration(mean: NonZeroU64, sigma: f64) -> Duration {
+    use rand_distr::Distribution;
+
+    let sigma = if sigma.is_finite() { sigma.max(0.0) } else { 0.0 };
+    // Pick mu so that the distribution's mean, exp(mu + sigma^2 / 2), is `mean`.
+    let mu = (mean.get() as f64).ln() - sigma * sigma / 2.0;
+    let lognormal = rand_distr::LogNormal::new(mu, sigma).expect("sigma is finite");
+    micros_f64(lognormal.sample(&mut rand::thread_rng()))
+}
+
+fn gen_pareto_duration(mean: NonZeroU64, shape: f64) -> Duration {
+    use rand_distr::Distribution;
+
+    let shape = if shape.is_finite() {
+        shape.max(1.0 + f64::EPSILON)
+    } else {
+        f64::MAX
+    };
+    // Pick the scale so that the distribution's mean, scale * shape / (shape - 1), is `mean`.
+    let scale = mean.get() as f64 * (1.0 - 1.0 / shape);
+    let pareto = rand_distr::Pareto::new(scale, shape).expect("scale and shape are positive");
+    micros_f64(pareto.sample(&mut rand::thread_rng()))
+}
+
+fn gen_bimodal_duration(short: u64, long: u64, p_long: f64) -> Duration {
+    use rand::Rng;
+
+    let p_long = if p_long.is_nan() { 0.0 } else { p_long.clamp(0.0, 1.0) };
+    if rand::thread_rng().gen_bool(p_long) {
+        Duration::from_micros(long)
+    } else {
+        Duration::from_micros(short)
+    }
+}
+
+const CACHE_LINE_WORDS: usize = 64 / std::mem::size_of::<usize>();
+
+/// Largest working set of a [`Work::PointerChase`] request, in KiB.
+pub const MAX_WORKING_SET_KIB: u64 = 1 << 20;
+
+/// Largest buffer of a [`Work::Stream`] request, in KiB.
+pub const MAX_STREAM_KIB: u64 = 1 << 20;
+
+/// Words of `T` in `kib` KiB, capped at `max_kib` since the size comes from the client.
+fn kib_to_words<T>(kib: u64, max_kib: u64) -> usize {
+    let bytes = kib.min(max_kib).saturating_mul(1024);
+    usize::try_from(bytes).unwrap_or(usize::MAX) / std::mem::size_of::<T>()
+}
+
+/// Link one pointer per cache line of `buf`, resized to `words`, into a single random cycle
+/// with Sattolo's algorithm.
+fn link_chase_cycle(buf: &mut Vec<usize>, words: usize) {
+    use rand::Rng;
+
+    let lines = words / CACHE_LINE_WORDS;
+    let mut order: Vec<usize> = (0..lines).collect();
+    let mut rng = rand::thread_rng();
+    for i in (1..lines).rev() {
+        order.swap(i, rng.gen_range(0..i));
+    }
+    buf.clear();
+    buf.resize(words, 0);
+    for (line, next) in order.iter().enumerate() {
+        buf[line * CACHE_LINE_WORDS] = next * CACHE_LINE_WORDS;
+    }
+}
+
+thread_local! {
+    static CHASE_BUF: std::cell::RefCell<Vec<usize>> = const { std::cell::RefCell::new(Vec::new()) };
+    static STREAM_BUF: std::cell::RefCell<Vec<u64>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Chase `steps` pointers through this thread's chase buffer, (re)building it if it isn't
+/// `working_set_kib` in size.
+///
+/// The buffer holds one pointer per cache line, linked into a single random cycle so that every
+/// step is a dependent load the prefetcher can't predict.
+fn chase_pointers(working_set_kib: u64, steps: u64) -> usize {
+    CHASE_BUF.with_borrow_mut(|buf| {
+        let words = kib_to_words::<usize>(working_set_kib, MAX_WORKING_SET_KIB).max(CACHE_LINE_WORDS);
+        if buf.len() != words {
+            link_chase_cycle(buf, words);
+        }
+
+        let mut idx = 0;
+        for _ in 0..steps {
+            idx = buf[idx];
+        }
+        std::hint::black_box(idx)
+    })
+}
+
+/// Sum this thread's stream buffer, (re)allocating it if it isn't `buffer_kib` in size.
+fn stream_buffer(buffer_kib: u64) -> u64 {
+    STREAM_BUF.with_borrow_mut(|buf| {
+        let words = kib_to_words::<u64>(buffer_kib, MAX_STREAM_KIB);
+        if buf.len() != words {
+            *buf = (0..words as u64).collect();
+        }
+        std::hint::black_box(buf.iter().fold(0u64, |acc, x| acc.wrapping_add(*x)))
+    })
+}
+
+/// How a [`Work::Lock`] request takes its lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LockKind {
+    Mutex,
+    /// Shared access to an `RwLock`.
+    Read,
+    /// Exclusive access to an `RwLock`.
+    Write,
+}
+
+/// Size of the server's pools of shared locks for [`Work::Lock`].
+pub const MAX_LOCKS: u64 = 1024;
+
+static MUTEXES: [std::sync::Mutex<()>; MAX_LOCKS as usize] =
+    [const { std::sync::Mutex::new(()) }; MAX_LOCKS as usize];
+static RW_LOCKS: [std::sync::RwLock<()>; MAX_LOCKS as usize] =
+    [const { std::sync::RwLock::new(()) }; MAX_LOCKS as usize];
+
+/// Take a random one of the first `locks` locks of `kind`'s pool and spin for `hold` inside it.
+fn contend(kind: LockKind, locks: u64, hold: Duration) {
+    use rand::Rng;
+
+    // Kept within the pool, since the count comes from the client.
+    let locks = locks.clamp(1, MAX_LOCKS);
+    let idx = rand::thread_rng().gen_range(0..locks) as usize;
+    match kind {
+        LockKind::Mutex => {
+            let _guard = MUTEXES[idx].lock().unwrap_or_else(|e| e.into_inner());
+            spin_for(hold);
+        }
+        LockKind::Read => {
+            let _guard = RW_LOCKS[idx].read().unwrap_or_else(|e| e.into_inner());
+            spin_for(hold);
+        }
+        LockKind::Write => {
+            let _guard = RW_LOCKS[idx].write().unwrap_or_else(|e| e.into_inner());
+            spin_for(hold);
+        }
+    }
+}
+
+fn spin_for(amt: Duration) {
+    let now = minstant::Instant::now();
+    while now.elapsed() < amt {}
+}
+
+/// Sleep until `amt` from now on the monotonic clock.
+///
+/// Sets this thread's timer slack to 1ns the first time it is called, so the kernel wakes the
+/// thread as close to the deadline as it can instead of coalescing the wakeup.
+fn precise_sleep(amt: Duration) {
+    thread_local! {
+        static SLACK_SET: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+    }
+    if !SLACK_SET.get() {
+        unsafe { libc::prctl(libc::PR_SET_TIMERSLACK, 1) };
+        SLACK_SET.set(true);
+    }
+
+    let mut deadline = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut deadline) };
+    let nsec = deadline.tv_nsec as u64 + amt.subsec_nanos() as u64;
+    deadline.tv_sec += (amt.as_secs() + nsec / 1_000_000_000) as libc::time_t;
+    deadline.tv_nsec = (nsec % 1_000_000_000) as _;
+    while unsafe {
+        libc::clock_nanosleep(
+            libc::CLOCK_MONOTONIC,
+            libc::TIMER_ABSTIME,
+            &deadline,
+            std::ptr::null_mut(),
+        )
+    } == libc::EINTR
+    {}
+}
+
+/// Size of the scratch file read by [`Work::FileRead`]. Larger reads wrap around.
+const SCRATCH_FILE_BYTES: u64 = 64 * 1024 * 1024;
+/// Largest read of a [`Work::FileRead`] request, in bytes.
+pub const MAX_FILE_READ_BYTES: u64 = 1 << 30;
+/// Alignment of offsets, lengths and buffers for `O_DIRECT` reads.
+const DIRECT_IO_ALIGN: usize = 4096;
+/// Largest single read issued by [`Work::FileRead`].
+const FILE_READ_CHUNK: usize = 1024 * 1024;
+
+static SCRATCH_DIR: OnceLock<PathBuf> = OnceLock::new();
+static SCRATCH_FILE: OnceLock<Result<PathBuf, String>> = OnceLock::new();
+
+/// Set the directory in which the [`Work::FileRead`] scratch file is created. Defaults to
+/// [`std::env::temp_dir`]. Must be called before the scratch file is created to have any effect.
+///
+/// `O_DIRECT` is not supported on tmpfs, so direct reads need a directory on a real filesystem.
+pub fn set_scratch_dir(dir: PathBuf) {
+    let _ = SCRATCH_DIR.set(dir);
+}
+
+/// Create the [`Work::FileRead`] scratch file, so that the first file read doesn't pay for
+/// writing it. Otherwise it's created by the first file read.
+pub fn create_scratch_file() -> Result<(), anyhow::Error> {
+    scratch_file()
+        .map(|_| ())
+        .map_err(|e| anyhow::anyhow!("{}", e))
+}
+
+fn scratch_file() -> Result<&'static PathBuf, &'static String> {
+    SCRATCH_FILE
+        .get_or_init(|| {
+            // The file is left behind for the next server to reuse rather than rewriting 64 MiB
+            // on every run. It is written under a temporary name so a concurrent server never
+            // sees a partial file.
+            let dir = SCRATCH_DIR.get_or_init(std::env::temp_dir);
+            let path = dir.join("woonsocket-scratch");
+            let create = || -> Result<(), std::io::Error> {
+                if std::fs::metadata(&path).is_ok_and(|m| m.len() == SCRATCH_FILE_BYTES) {
+                    return Ok(());
+                }
+                let tmp = dir.join(format!("woonsocket-scratch.{}", std::process::id()));
+                let mut f = File::create(&tmp)?;
+                let chunk: Vec<u8> = (0..FILE_READ_CHUNK).map(|i| i as u8).collect();
+                for _ in 0..SCRATCH_FILE_BYTES / FILE_READ_CHUNK as u64 {
+                    f.write_all(&chunk)?;
+                }
+                f.sync_all()?;
+                std::fs::rename(&tmp, &path)
+            };
+            create()
+                .map(|_| path.clone())
+                .map_err(|e| format!("creating scratch file {}: {}", path.display(), e))
+        })
+        .as_ref()
+}
+
+/// Read `bytes` from a random offset of the scratch file.
+fn read_scratch(bytes: u64, direct: bool) -> Result<(), anyhow::Error> {
+    use rand::Rng;
+
+    thread_local! {
+        static FILES: std::cell::RefCell<[Option<File>; 2]> = const {
+            std::cell::RefCell::new([None, None])
+        };
+    }
+
+    let path = scratch_file().map_err(|e| anyhow::anyhow!("{}", e))?;
+    FILES.with_borrow_mut(|files| {
+        let file = match &mut files[direct as usize] {
+            Some(f) => f,
+            slot => {
+                let mut opts = OpenOptions::new();
+                opts.read(true);
+                if direct {
+                    opts.custom_flags(libc::O_DIRECT);
+                }
+                slot.insert(opts.open(path)?)
+            }
+        };
+
+        // Direct IO needs aligned offsets, lengths and buffers, so round everything to the
+        // alignment and carve an aligned window out of an oversized buffer.
+        let align = if direct { DIRECT_IO_ALIGN as u64 } else { 1 };
+        // Capped, since the size comes from the client.
+        let bytes = bytes.min(MAX_FILE_READ_BYTES).div_ceil(align) * align;
+        let chunk_len = (bytes as usize).min(FILE_READ_CHUNK);
+        let mut buf = vec![0u8; chunk_len + DIRECT_IO_ALIGN];
+        let start = buf.as_ptr().align_offset(DIRECT_IO_ALIGN);
+        let buf = &mut buf[start..start + chunk_len];
+
+        let mut offset = rand::thread_rng().gen_range(0..SCRATCH_FILE_BYTES / align) * align;
+        let mut remaining = bytes;
+        while remaining > 0 {
+            let len = (remaining as usize)
+                .min(chunk_len)
+                .min((SCRATCH_FILE_BYTES - offset) as usize);
+            file.read_exact_at(&mut buf[..len], offset)?;
+            remaining -= len as u64;
+            offset = (offset + len as u64) % SCRATCH_FILE_BYTES;
+        }
+        std::hint::black_box(&buf);
+        Ok(())
+    })
+}
+
 impl Work {
+    /// Sample how long a time-based variant takes, without doing it. `None` for variants whose
+    /// duration isn't specified in time.
+    pub fn sample_duration(&self) -> Option<Duration> {
+        match *self {
+            Self::Immediate => Some(Duration::ZERO),
+            Self::Const(amt)
+            | Self::BusyTimeConst(amt)
+            | Self::Sleep(amt)
+            | Self::NanoSleep(amt) => Some(Duration::from_micros(amt)),
+            Self::Poisson(amt) => Some(gen_poisson_duration(amt)),
+            Self::Exponential(mean) => Some(gen_exponential_duration(mean)),
+            Self::LogNormal { mean, sigma } => Some(gen_lognormal_duration(mean, sigma)),
+            Self::Pareto { mean, shape } => Some(gen_pareto_duration(mean, shape)),
+            Self::Bimodal {
+                short,
+                long,
+                p_long,
+            } => Some(gen_bimodal_duration(short, long, p_long)),
+            _ => None,
+        }
+    }
+
     /// Perform the busy work.
     ///
-    /// Uses blocking calls for non-busy variants ([`Self::Const`] and [`Self::Poisson`]).
+    /// Time-based variants spin on the CPU, except [`Self::Sleep`] and [`Self::NanoSleep`], which
+    /// block. [`Self::FileRead`] blocks in `pread`, and [`Self::Lock`] may block waiting for its
+    /// lock.
+    ///
+    /// Panics on [`Self::FanOut`], which [`crate::serialize::ClientWorkPacket::do_work`] performs
+    /// itself so that it can report the downstream timing.
     pub fn perform(self) -> Option<Vec<u8>> {
         match self {
             Self::Immediate => None,
@@ -51,6 +504,67 @@ impl Work {
                 while now.elapsed() < amt {}
                 None
             }
+            Self::Exponential(mean) => {
+                spin_for(gen_exponential_duration(mean));
+                None
+            }
+            Self::LogNormal { mean, sigma } => {
+                spin_for(gen_lognormal_duration(mean, sigma));
+                None
+            }
+            Self::Pareto { mean, shape } => {
+                spin_for(gen_pareto_duration(mean, shape));
+                None
+            }
+            Self::Bimodal {
+                short,
+                long,
+                p_long,
+            } => {
+                spin_for(gen_bimodal_duration(short, long, p_long));
+                None
+            }
+            Self::PointerChase {
+                working_set_kib,
+                steps,
+            } => {
+                chase_pointers(working_set_kib, steps);
+                None
+            }
+            Self::Stream { buffer_kib } => {
+                stream_buffer(buffer_kib);
+                None
+            }
+            Self::Sleep(amt) => {
+                std::thread::sleep(Duration::from_micros(amt));
+                None
+            }
+            Self::NanoSleep(amt) => {
+                precise_sleep(Duration::from_micros(amt));
+                None
+            }
+            Self::FileRead { bytes, direct } => {
+                if let Err(e) = read_scratch(bytes, direct) {
+                    eprintln!("File read work failed: {:?}", e);
+                }
+                None
+            }
+            Self::Get(key) => {
+                // Values are not returned: responses must fit in a single message chunk.
+                std::hint::black_box(kv::store().get(key));
+                None
+            }
+            Self::Set { key, value_len } => {
+                kv::store().set(key, value_len);
+                None
+            }
+            Self::Lock { kind, locks, hold } => {
+                contend(kind, locks, Duration::from_micros(hold));
+                None
+            }
+            Self::FanOut { .. } => {
+                unreachable!("fan-out work is performed by ClientWorkPacket::do_work")
+            }
             Self::Payload => {
                 use rand::seq::SliceRandom;
                 let x = [64usize, 256, 512, 1024];
@@ -66,12 +580,11 @@ impl Work {
                 None
             }
             Self::BusyWorkConst(amt) => {
-                // from shenango:
-                // https://github.com/shenango/shenango/blob/master/apps/synthetic/src/fakework.rs#L54
-                let k = 2350845.545;
-                for i in 0..amt {
-                    std::hint::black_box(f64::sqrt(k * i as f64));
-                }
+                fake_work(amt);
+                None
+            }
+            Self::BusyWorkUs(amt) => {
+                fake_work((amt as f64 * calibrate_busy_work()) as u64);
                 None
             }
         }
@@ -95,13 +608,177 @@ impl std::str::FromStr for Work {
                 Ok(Work::BusyTimeConst(amt.parse()?))
             }
             [variant, amt] if *variant == "busywork" || *variant == "bw" => {
-                Ok(Work::BusyWorkConst(amt.parse()?))
+                match amt.strip_suffix("us") {
+                    Some(us) => Ok(Work::BusyWorkUs(us.parse()?)),
+                    None => Ok(Work::BusyWorkConst(amt.parse()?)),
+                }
+            }
+            [variant, amt] if *variant == "exponential" || *variant == "exp" => {
+                Ok(Work::Exponential(parse_mean(amt)?))
+            }
+            [variant, params] if *variant == "lognormal" => {
+                match params.split(',').collect::<Vec<_>>()[..] {
+                    [mean, sigma] => {
+                        let sigma: f64 = sigma.parse()?;
+                        if !(sigma >= 0.0 && sigma.is_finite()) {
+                            return Err(WorkParseErr::InvalidParam(
+                                "lognormal sigma must be a non-negative number",
+                            ));
+                        }
+                        Ok(Work::LogNormal {
+                            mean: parse_mean(mean)?,
+                            sigma,
+                        })
+                    }
+                    _ => Err(WorkParseErr::UnknownFmt(s.to_owned())),
+                }
+            }
+            [variant, params] if *variant == "pareto" => {
+                match params.split(',').collect::<Vec<_>>()[..] {
+                    [mean, shape] => {
+                        let shape: f64 = shape.parse()?;
+                        if !(shape > 1.0 && shape.is_finite()) {
+                            return Err(WorkParseErr::InvalidParam(
+                                "pareto shape must be greater than 1",
+                            ));
+                        }
+                        Ok(Work::Pareto {
+                            mean: parse_mean(mean)?,
+                            shape,
+                        })
+                    }
+                    _ => Err(WorkParseErr::UnknownFmt(s.to_owned())),
+                }
+            }
+            [variant, params] if *variant == "bimodal" => {
+                match params.split(',').collect::<Vec<_>>()[..] {
+                    [short, long, p_long] => {
+                        let p_long: f64 = p_long.parse()?;
+                        if !(0.0..=1.0).contains(&p_long) {
+                            return Err(WorkParseErr::InvalidParam(
+                                "bimodal p_long must be between 0 and 1",
+                            ));
+                        }
+                        Ok(Work::Bimodal {
+                            short: short.parse()?,
+                            long: long.parse()?,
+                            p_long,
+                        })
+                    }
+                    _ => Err(WorkParseErr::UnknownFmt(s.to_owned())),
+                }
+            }
+            [variant, params] if *variant == "chase" => {
+                match params.split(',').collect::<Vec<_>>()[..] {
+                    [working_set_kib, steps] => {
+                        let working_set_kib =
+                            parse_nonzero(working_set_kib, "chase working set size")?;
+                        if working_set_kib > MAX_WORKING_SET_KIB {
+                            return Err(WorkParseErr::InvalidParam(
+                                "chase working set must be at most 1048576 KiB",
+                            ));
+                        }
+                        Ok(Work::PointerChase {
+                            working_set_kib,
+                            steps: steps.parse()?,
+                        })
+                    }
+                    _ => Err(WorkParseErr::UnknownFmt(s.to_owned())),
+                }
+            }
+            [variant, amt] if *variant == "stream" => {
+                let buffer_kib = parse_nonzero(amt, "stream buffer size")?;
+                if buffer_kib > MAX_STREAM_KIB {
+                    return Err(WorkParseErr::InvalidParam(
+                        "stream buffer must be at most 1048576 KiB",
+                    ));
+                }
+                Ok(Work::Stream { buffer_kib })
+            }
+            [variant, amt] if *variant == "sleep" => Ok(Work::Sleep(amt.parse()?)),
+            [variant, amt] if *variant == "nanosleep" => Ok(Work::NanoSleep(amt.parse()?)),
+            [variant, params] if *variant == "fileread" => {
+                let (bytes, direct) = match params.split(',').collect::<Vec<_>>()[..] {
+                    [bytes] => (bytes, false),
+                    [bytes, "direct"] => (bytes, true),
+                    _ => return Err(WorkParseErr::UnknownFmt(s.to_owned())),
+                };
+                let bytes = parse_nonzero(bytes, "file read size")?;
+                if bytes > MAX_FILE_READ_BYTES {
+                    return Err(WorkParseErr::InvalidParam(
+                        "file read must be at most 1073741824 bytes",
+                    ));
+                }
+                Ok(Work::FileRead { bytes, direct })
+            }
+            [variant, params] if ["mutex", "rwread", "rwwrite"].contains(variant) => {
+                let kind = match *variant {
+                    "mutex" => LockKind::Mutex,
+                    "rwread" => LockKind::Read,
+                    _ => LockKind::Write,
+                };
+                match params.split(',').collect::<Vec<_>>()[..] {
+                    [locks, hold] => {
+                        let locks = parse_nonzero(locks, "number of locks")?;
+                        if locks > MAX_LOCKS {
+                            return Err(WorkParseErr::InvalidParam(
+                                "number of locks must be at most 1024",
+                            ));
+                        }
+                        Ok(Work::Lock {
+                            kind,
+                            locks,
+                            hold: hold.parse()?,
+                        })
+                    }
+                    _ => Err(WorkParseErr::UnknownFmt(s.to_owned())),
+                }
+            }
+            [variant, params] if *variant == "fanout" => {
+                match params.split(',').collect::<Vec<_>>()[..] {
+                    [fanout, quorum, leaf] => {
+                        let fanout = parse_nonzero(fanout, "fan-out")?;
+                        let quorum = quorum.parse()?;
+                        if !(1..=fanout).contains(&quorum) {
+                            return Err(WorkParseErr::InvalidParam(
+                                "fan-out quorum must be between 1 and the fan-out",
+                            ));
+                        }
+                        Ok(Work::FanOut {
+                            fanout,
+                            quorum,
+                            leaf: leaf.parse()?,
+                        })
+                    }
+                    _ => Err(WorkParseErr::UnknownFmt(s.to_owned())),
+                }
+            }
+            [variant, key] if *variant == "get" => Ok(Work::Get(key.parse()?)),
+            [variant, params] if *variant == "set" => {
+                match params.split(',').collect::<Vec<_>>()[..] {
+                    [key, value_len] => Ok(Work::Set {
+                        key: key.parse()?,
+                        value_len: kv::parse_value_len(value_len)?,
+                    }),
+                    _ => Err(WorkParseErr::UnknownFmt(s.to_owned())),
+                }
             }
             _ => Err(WorkParseErr::UnknownFmt(s.to_owned())),
         }
     }
 }
 
+fn parse_mean(amt: &str) -> Result<NonZeroU64, WorkParseErr> {
+    NonZeroU64::new(amt.parse()?).ok_or(WorkParseErr::ZeroMeanValue)
+}
+
+fn parse_nonzero(amt: &str, what: &'static str) -> Result<u64, WorkParseErr> {
+    match amt.parse()? {
+        0 => Err(WorkParseErr::ZeroSize(what)),
+        x => Ok(x),
+    }
+}
+
 impl std::fmt::Display for Work {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -111,6 +788,202 @@ impl std::fmt::Display for Work {
             Work::Payload => write!(f, "payload"),
             Work::BusyTimeConst(amt) => write!(f, "busytime:{}", amt),
             Work::BusyWorkConst(amt) => write!(f, "busywork:{}", amt),
+            Work::BusyWorkUs(amt) => write!(f, "busywork:{}us", amt),
+            Work::Exponential(mean) => write!(f, "exp:{}", mean),
+            Work::LogNormal { mean, sigma } => write!(f, "lognormal:{},{}", mean, sigma),
+            Work::Pareto { mean, shape } => write!(f, "pareto:{},{}", mean, shape),
+            Work::Bimodal {
+                short,
+                long,
+                p_long,
+            } => write!(f, "bimodal:{},{},{}", short, long, p_long),
+            Work::PointerChase {
+                working_set_kib,
+                steps,
+            } => write!(f, "chase:{},{}", working_set_kib, steps),
+            Work::Stream { buffer_kib } => write!(f, "stream:{}", buffer_kib),
+            Work::Sleep(amt) => write!(f, "sleep:{}", amt),
+            Work::NanoSleep(amt) => write!(f, "nanosleep:{}", amt),
+            Work::FileRead {
+                bytes,
+                direct: false,
+            } => write!(f, "fileread:{}", bytes),
+            Work::FileRead {
+                bytes,
+                direct: true,
+            } => write!(f, "fileread:{},direct", bytes),
+            Work::FanOut {
+                fanout,
+                quorum,
+                leaf,
+            } => write!(f, "fanout:{},{},{}", fanout, quorum, leaf),
+            Work::Get(key) => write!(f, "get:{}", key),
+            Work::Set { key, value_len } => write!(f, "set:{},{}", key, value_len),
+            Work::Lock { kind, locks, hold } => {
+                let kind = match kind {
+                    LockKind::Mutex => "mutex",
+                    LockKind::Read => "rwread",
+                    LockKind::Write => "rwwrite",
+                };
+                write!(f, "{}:{},{}", kind, locks, hold)
+            }
+        }
+    }
+}
+
+/// One class of a [`WorkMix`]: either a fixed [`Work`], or a [`KvWorkload`] that picks a new
+/// [`Work::Get`] or [`Work::Set`] for each request.
+///
+/// Implements [`FromStr`](std::str::FromStr) using the [`KvWorkload`] format for `kv:` specs and
+/// the [`Work`] format otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WorkClass {
+    Fixed(Work),
+    Kv(KvWorkload),
+}
+
+impl WorkClass {
+    fn sample(&self) -> Work {
+        match self {
+            Self::Fixed(work) => *work,
+            Self::Kv(kv) => kv.sample(&mut rand::thread_rng()),
+        }
+    }
+
+    /// Whether `work` is a request this class could generate.
+    pub fn generates(&self, work: &Work) -> bool {
+        match self {
+            Self::Fixed(fixed) => fixed == work,
+            Self::Kv(kv) => kv.generates(work),
+        }
+    }
+}
+
+impl From<Work> for WorkClass {
+    fn from(work: Work) -> Self {
+        Self::Fixed(work)
+    }
+}
+
+impl std::str::FromStr for WorkClass {
+    type Err = WorkParseErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("kv:") {
+            Ok(Self::Kv(s.parse()?))
+        } else {
+            Ok(Self::Fixed(s.parse()?))
+        }
+    }
+}
+
+impl std::fmt::Display for WorkClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fixed(work) => write!(f, "{}", work),
+            Self::Kv(kv) => write!(f, "{}", kv),
+        }
+    }
+}
+
+/// A weighted mix of [`WorkClass`]es, one of which is sampled for each request.
+///
+/// Implements [`FromStr`](std::str::FromStr). String format is either a single [`WorkClass`], or
+/// `mix:[class]=[weight],[class]=[weight],...` where each `class` uses the [`WorkClass`] format
+/// and each `weight` is a positive f64. Weights need not sum to 1.
+#[derive(Debug, Clone)]
+pub struct WorkMix {
+    classes: Vec<(WorkClass, f64)>,
+    dist: rand::distributions::WeightedIndex<f64>,
+}
+
+impl WorkMix {
+    pub fn single(work: impl Into<WorkClass>) -> Self {
+        Self::new(vec![(work.into(), 1.0)]).unwrap()
+    }
+
+    fn new(classes: Vec<(WorkClass, f64)>) -> Result<Self, WorkParseErr> {
+        let dist = rand::distributions::WeightedIndex::new(classes.iter().map(|(_, w)| *w))
+            .map_err(|_| WorkParseErr::InvalidParam("mix weights must be positive"))?;
+        Ok(Self { classes, dist })
+    }
+
+    /// Pick the [`Work`] for the next request.
+    pub fn sample(&self) -> Work {
+        use rand::distributions::Distribution;
+
+        match &self.classes[..] {
+            [(class, _)] => class.sample(),
+            classes => classes[self.dist.sample(&mut rand::thread_rng())]
+                .0
+                .sample(),
+        }
+    }
+
+    /// The classes in this mix, in the order they were specified.
+    pub fn classes(&self) -> impl Iterator<Item = WorkClass> + '_ {
+        self.classes.iter().map(|(class, _)| *class)
+    }
+
+    /// The first class in this mix that could have generated `work`.
+    pub fn class_of(&self, work: &Work) -> Option<usize> {
+        self.classes
+            .iter()
+            .position(|(class, _)| class.generates(work))
+    }
+
+    /// Whether this mix has more than one class.
+    pub fn is_mixed(&self) -> bool {
+        self.classes.len() > 1
+    }
+}
+
+impl From<Work> for WorkMix {
+    fn from(work: Work) -> Self {
+        Self::single(work)
+    }
+}
+
+impl std::str::FromStr for WorkMix {
+    type Err = WorkParseErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(spec) = s.strip_prefix("mix:") else {
+            return Ok(Self::single(s.parse::<WorkClass>()?));
+        };
+
+        // Work formats may themselves contain commas (e.g. `bimodal:10,1000,0.01`), so a class
+        // only ends at a comma-separated token that carries its `=weight`.
+        let mut classes = Vec::new();
+        let mut pending = Vec::new();
+        for tok in spec.split(',') {
+            pending.push(tok);
+            if tok.contains('=') {
+                let class = pending.join(",");
+                let (work, weight) = class.rsplit_once('=').unwrap();
+                classes.push((work.parse()?, weight.parse()?));
+                pending.clear();
+            }
+        }
+        if !pending.is_empty() || classes.is_empty() {
+            return Err(WorkParseErr::UnknownFmt(s.to_owned()));
+        }
+        Self::new(classes)
+    }
+}
+
+impl std::fmt::Display for WorkMix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.classes[..] {
+            [(work, _)] => write!(f, "{}", work),
+            classes => {
+                write!(f, "mix:")?;
+                for (i, (work, weight)) in classes.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}={}", work, weight)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -122,8 +995,16 @@ pub enum WorkParseErr {
     UnknownFmt(String),
     /// Specified a Poisson distribution with lambda = 0.
     ZeroPoissonValue,
+    /// Specified a service time distribution with a mean of 0.
+    ZeroMeanValue,
+    /// Specified a zero size or count where at least one is needed.
+    ZeroSize(&'static str),
     /// Followed `type:amount`, but `amount` wasn't a `u64`.
     U64Parse(ParseIntError),
+    /// A distribution parameter that should be an `f64` wasn't.
+    F64Parse(ParseFloatError),
+    /// A distribution parameter was out of range.
+    InvalidParam(&'static str),
 }
 
 impl From<ParseIntError> for WorkParseErr {
@@ -132,18 +1013,36 @@ impl From<ParseIntError> for WorkParseErr {
     }
 }
 
+impl From<ParseFloatError> for WorkParseErr {
+    fn from(value: ParseFloatError) -> Self {
+        Self::F64Parse(value)
+    }
+}
+
 impl std::fmt::Display for WorkParseErr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::UnknownFmt(s) => {
-                write!(f, "Unknown work format specification {}. Format is [immedate|const|poisson|busytime|busywork]:[amount].", s)
+                write!(f, "Unknown work format specification {}. Format is [immediate|const|poisson|busytime|busywork|exp|stream|sleep|nanosleep|get]:[amount] or [lognormal|pareto|bimodal|chase|fileread|set|mutex|rwread|rwwrite|fanout]:[params].", s)
             }
             Self::ZeroPoissonValue => {
                 write!(f, "Poisson-distributed work amount must be nonzero.")
             }
+            Self::ZeroMeanValue => {
+                write!(f, "Distributed work mean must be nonzero.")
+            }
+            Self::ZeroSize(what) => {
+                write!(f, "The {} must be nonzero.", what)
+            }
             Self::U64Parse(n) => {
                 write!(f, "Could not parse work amount {} as u64.", n)
             }
+            Self::F64Parse(n) => {
+                write!(f, "Could not parse work parameter {} as f64.", n)
+            }
+            Self::InvalidParam(msg) => {
+                write!(f, "Invalid work parameter: {}.", msg)
+            }
         }
     }
 }
@@ -152,7 +1051,11 @@ impl std::error::Error for WorkParseErr {}
 
 #[cfg(test)]
 mod t {
-    use super::{Work, WorkParseErr};
+    use super::{
+        link_chase_cycle, LockKind, Work, WorkClass, WorkMix, WorkParseErr, CACHE_LINE_WORDS,
+        MAX_FILE_READ_BYTES,
+    };
+    use std::{num::NonZeroU64, time::Duration};
 
     #[test]
     fn parse_work_immediate() {
@@ -251,5 +1154,385 @@ mod t {
             "busywork:foo".parse::<Work>(),
             Err(WorkParseErr::U64Parse(_))
         ));
+
+        assert!(matches!(
+            "bw:50us".parse().expect("parse BusyWorkUs"),
+            Work::BusyWorkUs(50)
+        ));
+
+        assert!(matches!(
+            "busywork:foous".parse::<Work>(),
+            Err(WorkParseErr::U64Parse(_))
+        ));
+    }
+
+    #[test]
+    fn parse_work_exponential() {
+        assert!(matches!(
+            "exp:10".parse().expect("parse exponential"),
+            Work::Exponential(x) if x.get() == 10
+        ));
+
+        assert!(matches!(
+            "exponential:10".parse().expect("parse exponential"),
+            Work::Exponential(x) if x.get() == 10
+        ));
+
+        assert!(matches!(
+            "exp:0".parse::<Work>(),
+            Err(WorkParseErr::ZeroMeanValue)
+        ));
+    }
+
+    #[test]
+    fn parse_work_lognormal() {
+        assert!(matches!(
+            "lognormal:10,1.5".parse().expect("parse lognormal"),
+            Work::LogNormal { mean, sigma } if mean.get() == 10 && sigma == 1.5
+        ));
+
+        assert!(matches!(
+            "lognormal:10".parse::<Work>(),
+            Err(WorkParseErr::UnknownFmt(_))
+        ));
+
+        assert!(matches!(
+            "lognormal:10,-1".parse::<Work>(),
+            Err(WorkParseErr::InvalidParam(_))
+        ));
+    }
+
+    #[test]
+    fn parse_work_pareto() {
+        assert!(matches!(
+            "pareto:10,2.5".parse().expect("parse pareto"),
+            Work::Pareto { mean, shape } if mean.get() == 10 && shape == 2.5
+        ));
+
+        assert!(matches!(
+            "pareto:10,1".parse::<Work>(),
+            Err(WorkParseErr::InvalidParam(_))
+        ));
+
+        assert!(matches!(
+            "pareto:10,foo".parse::<Work>(),
+            Err(WorkParseErr::F64Parse(_))
+        ));
+    }
+
+    #[test]
+    fn parse_work_bimodal() {
+        assert!(matches!(
+            "bimodal:10,1000,0.01".parse().expect("parse bimodal"),
+            Work::Bimodal { short: 10, long: 1000, p_long } if p_long == 0.01
+        ));
+
+        assert!(matches!(
+            "bimodal:10,1000,2".parse::<Work>(),
+            Err(WorkParseErr::InvalidParam(_))
+        ));
+
+        assert!(matches!(
+            "bimodal:10,1000".parse::<Work>(),
+            Err(WorkParseErr::UnknownFmt(_))
+        ));
+    }
+
+    #[test]
+    fn parse_work_memory() {
+        assert!(matches!(
+            "chase:1024,100".parse().expect("parse chase"),
+            Work::PointerChase {
+                working_set_kib: 1024,
+                steps: 100
+            }
+        ));
+
+        assert!(matches!(
+            "chase:0,100".parse::<Work>(),
+            Err(WorkParseErr::ZeroSize(_))
+        ));
+
+        assert!(matches!(
+            "stream:4096".parse().expect("parse stream"),
+            Work::Stream { buffer_kib: 4096 }
+        ));
+
+        assert!(matches!(
+            "stream:0".parse::<Work>(),
+            Err(WorkParseErr::ZeroSize(_))
+        ));
+
+        for s in ["chase:1048577,1", "stream:1048577", "set:1,1048577"] {
+            assert!(matches!(s.parse::<Work>(), Err(WorkParseErr::InvalidParam(_))), "{}", s);
+        }
+        assert!("chase:18446744073709551615,1".parse::<Work>().is_err());
+    }
+
+    #[test]
+    fn chase_is_one_cycle() {
+        let lines = 1000;
+        let mut buf = Vec::new();
+        link_chase_cycle(&mut buf, lines * CACHE_LINE_WORDS);
+        let mut seen = vec![false; lines];
+        let mut idx = 0;
+        for _ in 0..lines {
+            assert!(!seen[idx / CACHE_LINE_WORDS], "revisited line {}", idx / CACHE_LINE_WORDS);
+            seen[idx / CACHE_LINE_WORDS] = true;
+            idx = buf[idx];
+        }
+        assert_eq!(idx, 0);
+        assert!(seen.iter().all(|&s| s));
+    }
+
+    #[test]
+    fn parse_work_blocking() {
+        assert!(matches!(
+            "sleep:10".parse().expect("parse sleep"),
+            Work::Sleep(10)
+        ));
+
+        assert!(matches!(
+            "nanosleep:10".parse().expect("parse nanosleep"),
+            Work::NanoSleep(10)
+        ));
+
+        assert!(matches!(
+            "fileread:4096,direct".parse().expect("parse fileread"),
+            Work::FileRead {
+                bytes: 4096,
+                direct: true
+            }
+        ));
+
+        assert!(matches!(
+            "fileread:4096,foo".parse::<Work>(),
+            Err(WorkParseErr::UnknownFmt(_))
+        ));
+
+        assert!(matches!(
+            "fileread:0".parse::<Work>(),
+            Err(WorkParseErr::ZeroSize(_))
+        ));
+
+        for direct in ["", ",direct"] {
+            let at_max = format!("fileread:{}{}", MAX_FILE_READ_BYTES, direct);
+            assert!(matches!(
+                at_max.parse().expect("parse fileread"),
+                Work::FileRead { bytes: MAX_FILE_READ_BYTES, .. }
+            ));
+            for bytes in [MAX_FILE_READ_BYTES + 1, u64::MAX] {
+                assert!(matches!(
+                    format!("fileread:{}{}", bytes, direct).parse::<Work>(),
+                    Err(WorkParseErr::InvalidParam(_))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn parse_work_kv() {
+        assert!(matches!("get:7".parse().expect("parse get"), Work::Get(7)));
+
+        assert!(matches!(
+            "set:7,128".parse().expect("parse set"),
+            Work::Set {
+                key: 7,
+                value_len: 128
+            }
+        ));
+
+        assert!(matches!(
+            "set:7".parse::<Work>(),
+            Err(WorkParseErr::UnknownFmt(_))
+        ));
+    }
+
+    #[test]
+    fn parse_work_lock() {
+        assert!(matches!(
+            "mutex:8,20".parse().expect("parse mutex"),
+            Work::Lock {
+                kind: LockKind::Mutex,
+                locks: 8,
+                hold: 20
+            }
+        ));
+
+        assert!(matches!(
+            "rwread:1,5".parse().expect("parse rwread"),
+            Work::Lock {
+                kind: LockKind::Read,
+                locks: 1,
+                hold: 5
+            }
+        ));
+
+        assert!(matches!(
+            "rwwrite:0,5".parse::<Work>(),
+            Err(WorkParseErr::ZeroSize(_))
+        ));
+
+        assert!(matches!(
+            "mutex:1025,5".parse::<Work>(),
+            Err(WorkParseErr::InvalidParam(_))
+        ));
+
+        assert!(matches!(
+            "mutex:8".parse::<Work>(),
+            Err(WorkParseErr::UnknownFmt(_))
+        ));
+    }
+
+    #[test]
+    fn distributions_off_the_wire() {
+        let mean = NonZeroU64::new(10).unwrap();
+        // Parameters the parser would reject can still arrive in a packet.
+        let mut works = Vec::new();
+        for x in [-1.0, 0.0, 1.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e300] {
+            works.push(Work::LogNormal { mean, sigma: x });
+            works.push(Work::Pareto { mean, shape: x });
+            works.push(Work::Bimodal {
+                short: 1,
+                long: 2,
+                p_long: x,
+            });
+        }
+        for work in works {
+            let d = work.sample_duration().expect("time-based");
+            assert!(d < Duration::from_secs(1), "{:?}", work);
+        }
+        for p_long in [-1.0, 2.0, f64::NAN] {
+            assert!(Work::Bimodal {
+                short: 1,
+                long: 2,
+                p_long
+            }
+            .perform()
+            .is_none());
+        }
+    }
+
+    #[test]
+    fn lock_counts_off_the_wire() {
+        // Counts the parser would reject can still arrive in a packet.
+        for locks in [0, 5000] {
+            for kind in [LockKind::Mutex, LockKind::Read, LockKind::Write] {
+                let work = Work::Lock {
+                    kind,
+                    locks,
+                    hold: 0,
+                };
+                assert!(work.perform().is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn parse_work_fanout() {
+        assert!(matches!(
+            "fanout:8,6,20".parse().expect("parse fanout"),
+            Work::FanOut {
+                fanout: 8,
+                quorum: 6,
+                leaf: 20
+            }
+        ));
+
+        assert!(matches!(
+            "fanout:0,0,20".parse::<Work>(),
+            Err(WorkParseErr::ZeroSize(_))
+        ));
+
+        assert!(matches!(
+            "fanout:4,5,20".parse::<Work>(),
+            Err(WorkParseErr::InvalidParam(_))
+        ));
+
+        assert!(matches!(
+            "fanout:4,0,20".parse::<Work>(),
+            Err(WorkParseErr::InvalidParam(_))
+        ));
+    }
+
+    #[test]
+    fn chase_visits_whole_cycle() {
+        // 4 KiB holds 64 cache lines, so a full cycle returns to the start after 64 steps.
+        assert_eq!(super::chase_pointers(4, 64), 0);
+        assert_ne!(super::chase_pointers(4, 63), 0);
+    }
+
+    #[test]
+    fn work_display_roundtrip() {
+        for s in [
+            "exp:10",
+            "lognormal:10,1.5",
+            "pareto:10,2.5",
+            "bimodal:10,1000,0.01",
+            "chase:1024,100",
+            "stream:4096",
+            "busywork:50us",
+            "sleep:10",
+            "nanosleep:10",
+            "get:42",
+            "set:42,100",
+            "mutex:4,10",
+            "rwread:1,10",
+            "rwwrite:1024,0",
+            "fanout:4,3,10",
+            "fileread:4096",
+            "fileread:4096,direct",
+        ] {
+            let work: Work = s.parse().expect("parse work");
+            assert_eq!(work.to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parse_work_mix() {
+        let mix: WorkMix = "mix:imm=0.7,const:50=0.25,const:1000=0.05"
+            .parse()
+            .expect("parse mix");
+        assert!(mix.is_mixed());
+        assert_eq!(
+            mix.classes().collect::<Vec<_>>(),
+            vec![
+                WorkClass::Fixed(Work::Immediate),
+                WorkClass::Fixed(Work::Const(50)),
+                WorkClass::Fixed(Work::Const(1000))
+            ]
+        );
+        assert_eq!(mix.to_string(), "mix:imm=0.7,const:50=0.25,const:1000=0.05");
+
+        let mix: WorkMix = "mix:bimodal:10,1000,0.01=1,imm=1"
+            .parse()
+            .expect("parse mix with commas");
+        assert_eq!(mix.classes().count(), 2);
+
+        let single: WorkMix = "const:5".parse().expect("parse single");
+        assert!(!single.is_mixed());
+        assert_eq!(single.sample(), Work::Const(5));
+
+        let mix: WorkMix = "mix:kv:100,0.9,32,0.99=9,const:5=1"
+            .parse()
+            .expect("parse mix with kv");
+        assert!(matches!(mix.classes().next(), Some(WorkClass::Kv(_))));
+        assert_eq!(mix.class_of(&Work::Get(3)), Some(0));
+        assert_eq!(mix.class_of(&Work::Const(5)), Some(1));
+        assert_eq!(mix.class_of(&Work::Get(100)), None);
+        assert_eq!(mix.to_string(), "mix:kv:100,0.9,32,0.99=9,const:5=1");
+
+        assert!(matches!(
+            "mix:imm=0.5,const:5".parse::<WorkMix>(),
+            Err(WorkParseErr::UnknownFmt(_))
+        ));
+        assert!(matches!(
+            "mix:imm=0,const:5=0".parse::<WorkMix>(),
+            Err(WorkParseErr::InvalidParam(_))
+        ));
+        assert!(matches!(
+            "mix:foo=1".parse::<WorkMix>(),
+            Err(WorkParseErr::UnknownFmt(_))
+        ));
     }
 }
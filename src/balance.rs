@@ -0,0 +1,273 @@
+//! Client-side load balancing over several servers, e.g. a small replicated fleet of servers on
+//! one host.
+
+use crate::{
+    app::Work,
+    socket::{Endpoint, SocketOptions},
+};
+use rand::Rng;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    io,
+    net::{SocketAddr, TcpStream},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+/// Points each server has on the consistent hashing ring, so that keys spread evenly.
+const VNODES: usize = 100;
+
+/// How a client picks among its servers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Each server in turn.
+    #[default]
+    RoundRobin,
+    /// A server chosen uniformly at random.
+    Random,
+    /// Whichever of two servers chosen at random has fewer outstanding requests.
+    PowerOfTwo,
+    /// The server that owns the key on a hash ring, so that a key keeps going to the same server
+    /// and only a fraction of keys move when servers are added or removed. Requests are keyed by
+    /// their get or set key and connections by their index. Requests without a key go to a
+    /// random server.
+    ConsistentHash,
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RoundRobin => write!(f, "rr"),
+            Self::Random => write!(f, "random"),
+            Self::PowerOfTwo => write!(f, "p2c"),
+            Self::ConsistentHash => write!(f, "hash"),
+        }
+    }
+}
+
+/// What a [`Policy`] places on a server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Granularity {
+    /// Each connection, for as long as it's open. Sessions that reconnect are placed again.
+    #[default]
+    Connection,
+    /// Each request. Every client connection then has a socket to each server.
+    Request,
+}
+
+impl std::fmt::Display for Granularity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connection => write!(f, "connection"),
+            Self::Request => write!(f, "request"),
+        }
+    }
+}
+
+/// The servers a client sends to, and how it spreads its load over them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Servers {
+    pub addrs: Vec<SocketAddr>,
+    pub policy: Policy,
+    pub granularity: Granularity,
+}
+
+impl Servers {
+    /// Just the server at `addr`.
+    pub fn single(addr: SocketAddr) -> Self {
+        Self {
+            addrs: vec![addr],
+            policy: Policy::default(),
+            granularity: Granularity::default(),
+        }
+    }
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// One run's view of its [`Servers`]: how to connect to each, and how loaded each is.
+pub struct Balancer {
+    addrs: Vec<SocketAddr>,
+    options: SocketOptions,
+    policy: Policy,
+    granularity: Granularity,
+    /// The next server for round robin.
+    next: AtomicUsize,
+    /// Requests sent to each server and not yet answered.
+    outstanding: Vec<AtomicU64>,
+    /// Points on the hash ring and the servers that own them, sorted.
+    ring: Vec<(u64, usize)>,
+}
+
+impl Balancer {
+    /// A balancer over `servers`, connecting with `options`.
+    pub fn new(servers: &Servers, options: SocketOptions) -> Self {
+        assert!(
+            !servers.addrs.is_empty(),
+            "A client needs at least one server"
+        );
+        let mut ring: Vec<_> = servers
+            .addrs
+            .iter()
+            .enumerate()
+            .flat_map(|(i, addr)| (0..VNODES).map(move |v| (hash((addr, v)), i)))
+            .collect();
+        ring.sort_unstable();
+        Self {
+            addrs: servers.addrs.clone(),
+            options,
+            policy: servers.policy,
+            granularity: servers.granularity,
+            next: AtomicUsize::new(0),
+            outstanding: servers.addrs.iter().map(|_| AtomicU64::new(0)).collect(),
+            ring,
+        }
+    }
+
+    pub fn num_servers(&self) -> usize {
+        self.addrs.len()
+    }
+
+    /// Options every socket is opened with.
+    pub fn options(&self) -> SocketOptions {
+        self.options
+    }
+
+    /// How many sockets [`Balancer::connect`] opens for each client connection.
+    pub fn sockets_per_conn(&self) -> usize {
+        match self.granularity {
+            Granularity::Connection => 1,
+            Granularity::Request => self.num_servers(),
+        }
+    }
+
+    /// Open the sockets client connection `conn` sends over, each with the index of its server:
+    /// one to every server if requests are balanced, or else one to the server `conn` is placed
+    /// on.
+    pub fn connect(&self, conn: usize) -> io::Result<Vec<(usize, TcpStream)>> {
+        let servers = match self.granularity {
+            Granularity::Connection => vec![self.pick(Some(conn as u64))],
+            Granularity::Request => (0..self.num_servers()).collect(),
+        };
+        servers
+            .into_iter()
+            .map(|i| {
+                let endpoint = Endpoint {
+                    addr: self.addrs[i],
+                    options: self.options,
+                };
+                Ok((i, endpoint.connect()?))
+            })
+            .collect()
+    }
+
+    /// Which of a client connection's sockets, as opened by [`Balancer::connect`], to send
+    /// `work` over.
+    pub fn route(&self, work: &Work) -> usize {
+        match self.granularity {
+            Granularity::Connection => 0,
+            Granularity::Request => match work {
+                Work::Get(key) | Work::Set { key, .. } => self.pick(Some(*key)),
+                _ => self.pick(None),
+            },
+        }
+    }
+
+    /// Pick a server for something keyed by `key`, if it has a key.
+    fn pick(&self, key: Option<u64>) -> usize {
+        let n = self.num_servers();
+        if n == 1 {
+            return 0;
+        }
+        let mut rng = rand::thread_rng();
+        match (self.policy, key) {
+            (Policy::RoundRobin, _) => self.next.fetch_add(1, Ordering::Relaxed) % n,
+            (Policy::PowerOfTwo, _) => {
+                let a = rng.gen_range(0..n);
+                let b = (a + rng.gen_range(1..n)) % n;
+                let load = |i: usize| self.outstanding[i].load(Ordering::Relaxed);
+                if load(b) < load(a) {
+                    b
+                } else {
+                    a
+                }
+            }
+            (Policy::ConsistentHash, Some(key)) => {
+                let h = hash(key);
+                let i = self.ring.partition_point(|&(point, _)| point < h);
+                self.ring[i % self.ring.len()].1
+            }
+            (Policy::Random | Policy::ConsistentHash, _) => rng.gen_range(0..n),
+        }
+    }
+
+    /// Note a request sent to server `i`.
+    pub fn sent(&self, i: usize) {
+        self.outstanding[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Note that `count` requests sent to server `i` were answered, or given up on.
+    pub fn completed(&self, i: usize, count: u64) {
+        let _ = self.outstanding[i].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+            Some(n.saturating_sub(count))
+        });
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::{Balancer, Granularity, Policy, Servers};
+    use crate::{app::Work, socket::SocketOptions};
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    fn balancer(n: u16, policy: Policy) -> Balancer {
+        let servers = Servers {
+            addrs: (0..n)
+                .map(|i| SocketAddr::from((Ipv4Addr::LOCALHOST, 9000 + i)))
+                .collect(),
+            policy,
+            granularity: Granularity::Request,
+        };
+        Balancer::new(&servers, SocketOptions::default())
+    }
+
+    #[test]
+    fn round_robin_and_power_of_two() {
+        let b = balancer(3, Policy::RoundRobin);
+        let picks: Vec<_> = (0..6).map(|_| b.route(&Work::Immediate)).collect();
+        assert_eq!(picks, [0, 1, 2, 0, 1, 2]);
+
+        let b = balancer(2, Policy::PowerOfTwo);
+        b.sent(0);
+        assert!((0..20).all(|_| b.route(&Work::Immediate) == 1));
+        b.completed(0, 5);
+        b.sent(1);
+        assert!((0..20).all(|_| b.route(&Work::Immediate) == 0));
+    }
+
+    #[test]
+    fn consistent_hash() {
+        let three = balancer(3, Policy::ConsistentHash);
+        let four = balancer(4, Policy::ConsistentHash);
+        let keys = 0..3000;
+        let placed: Vec<_> = keys.clone().map(|k| three.route(&Work::Get(k))).collect();
+        // Keys stay put, and reads and writes of a key go to the same server.
+        let set = |key| Work::Set { key, value_len: 8 };
+        assert!(keys.clone().all(|k| three.route(&set(k)) == placed[k as usize]));
+        for i in 0..3 {
+            assert!(placed.iter().filter(|&&p| p == i).count() > 500);
+        }
+        // A fourth server takes about a quarter of the keys, and the rest stay put.
+        let moved: Vec<_> = keys
+            .map(|k| four.route(&Work::Get(k)))
+            .zip(&placed)
+            .filter(|(now, before)| now != *before)
+            .collect();
+        assert!(moved.iter().all(|&(now, _)| now == 3));
+        assert!((400..1200).contains(&moved.len()), "{} keys moved", moved.len());
+    }
+}
@@ -1,56 +1,578 @@
-use clap::Parser;
-use netapis_s25_dev::{app::Work, closed_loop_client, open_loop_client};
+use clap::{
+    error::ErrorKind, ArgAction, ArgGroup, Args, CommandFactory, Parser, Subcommand, ValueEnum,
+};
+use netapis_s25_dev::{
+    affinity::{self, CpuList, Placement},
+    app::WorkMix,
+    balance::{Granularity, Policy, Servers},
+    capacity::{self, SearchConfig, Slo},
+    closed_loop_client::{self, ThinkTime, UserModel},
+    engine::{Backend, Clients, UringConfig},
+    manifest::Manifest,
+    metrics::{MeasurementConfig, MeasurementWindow},
+    open_loop_client,
+    profile::{LoadProfile, Shape},
+    socket::{self, SocketOptions},
+    sweep::{self, Pacing, Steps, SweepKind},
+    trace::Trace,
+};
 use std::{
-    net::{Ipv4Addr, SocketAddrV4},
+    net::SocketAddr,
+    num::NonZeroU64,
     path::PathBuf,
     time::Duration,
 };
 
+#[derive(Copy, Clone, Debug, ValueEnum, Eq, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum ClientBackend {
+    threads,
+    epoll,
+    uring,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum, Eq, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum CpuPlacement {
+    shared,
+    spread,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum, Eq, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum BalancePolicy {
+    rr,
+    random,
+    p2c,
+    hash,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum, Eq, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum BalanceGranularity {
+    connection,
+    request,
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about=None)]
 pub struct Opt {
     #[arg(long, help = "Specify this argument for an open loop client")]
     interval_us: Option<u64>,
 
+    #[arg(
+        long,
+        conflicts_with = "interval_us",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Total open loop request rate in req/s, split evenly across threads"
+    )]
+    rate: Option<u64>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["interval_us", "rate"],
+        help = "Replay an open loop trace file of [arrival_us] [work] [payload_bytes] lines"
+    )]
+    trace: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["interval_us", "rate", "trace"],
+        help = "Vary the open loop total rate over the run: const:r, ramp:a,b, onoff:r,burst_ms,duty, steps:r/secs,..., or sine:mean,amp,period_secs"
+    )]
+    profile: Option<Shape>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["interval_us", "rate", "trace", "profile"],
+        help = "Like --profile, from a file of [secs] [shape] lines run one after another"
+    )]
+    profile_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["interval_us", "rate", "trace", "profile", "profile_file"],
+        help = "Closed loop think time after each response, e.g. const:[us] or exp:[mean_us]"
+    )]
+    think: Option<ThinkTime>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["interval_us", "rate", "trace", "profile", "profile_file"],
+        help = "Closed loop requests per session; each session uses its own connection"
+    )]
+    session_requests: Option<NonZeroU64>,
+
+    #[arg(
+        long,
+        requires = "session_requests",
+        help = "Idle time between closed loop sessions, e.g. const:[us] or exp:[mean_us]"
+    )]
+    session_gap: Option<ThinkTime>,
+
     #[arg(short, long)]
     num_threads: u64,
 
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Spread this many connections over the --num-threads threads (epoll and uring backends)"
+    )]
+    connections: Option<u64>,
+
+    #[arg(
+        long,
+        help = "What drives the connections: one blocking connection per thread, epoll or io_uring (default: epoll with --connections, threads otherwise)"
+    )]
+    backend: Option<ClientBackend>,
+
+    #[arg(long, help = "Have a kernel thread poll the io_uring submission queue")]
+    sqpoll: bool,
+
+    #[arg(long, help = "Register io_uring buffers with the kernel up front")]
+    registered_buffers: bool,
+
     #[arg(short, long)]
     runtime_secs: u64,
 
-    #[arg(short, long)]
-    ip: Ipv4Addr,
+    #[arg(
+        short,
+        long,
+        required_unless_present = "servers",
+        help = "Server hostname, or IPv4 or IPv6 address"
+    )]
+    ip: Option<String>,
 
-    #[arg(short, long)]
-    port: u16,
+    #[arg(short, long, required_unless_present = "servers")]
+    port: Option<u16>,
 
-    #[arg(short, long)]
-    work: Work,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = socket::resolve_host_port,
+        conflicts_with_all = ["ip", "port"],
+        help = "Balance load over these servers instead, e.g. localhost:8080,[::1]:8081"
+    )]
+    servers: Vec<SocketAddr>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = BalancePolicy::rr,
+        requires = "servers",
+        help = "How --servers are picked: round robin, random, the less loaded of two random ones, or by consistent hashing of the connection or get/set key"
+    )]
+    balance: BalancePolicy,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = BalanceGranularity::connection,
+        requires = "servers",
+        help = "Place each connection on one of the --servers, or each request"
+    )]
+    balance_per: BalanceGranularity,
+
+    #[arg(
+        short,
+        long,
+        required_unless_present = "trace",
+        help = "Work for each request, or a weighted mix:[work]=[weight],..."
+    )]
+    work: Option<WorkMix>,
 
     #[arg(short, long)]
     outpath: PathBuf,
+
+    #[arg(long, default_value_t = 100, help = "Exclude requests sent in the first N ms from statistics")]
+    warmup_ms: u64,
+
+    #[arg(long, default_value_t = 0, help = "Exclude requests sent in the last N ms from statistics")]
+    cooldown_ms: u64,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Write per-interval metrics to <outpath>/timeseries.csv every N ms"
+    )]
+    sample_interval_ms: Option<u64>,
+
+    #[arg(long, requires = "sample_interval_ms", help = "Print per-interval metrics as they are sampled")]
+    live: bool,
+
+    #[arg(long, help = "Write each open loop run's requests to <outpath>/trace.txt for replay")]
+    record_trace: bool,
+
+    #[arg(long, help = "Sample each connection's TCP_INFO to <outpath>/tcp_info.csv every N ms")]
+    tcp_info_ms: Option<u64>,
+
+    #[arg(long, help = "Pin client threads to these CPUs, e.g. 4-7 or 4,6")]
+    client_cpus: Option<CpuList>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = CpuPlacement::shared,
+        requires = "client_cpus",
+        help = "Let pinned threads share all of the CPUs, or spread them one per CPU round robin"
+    )]
+    cpu_placement: CpuPlacement,
+
+    #[command(flatten)]
+    sockets: SocketOpt,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Socket options")]
+struct SocketOpt {
+    #[arg(
+        long,
+        default_value_t = true,
+        action = ArgAction::Set,
+        help = "Set TCP_NODELAY, so that Nagle's algorithm doesn't hold back small writes. On by default for every client backend"
+    )]
+    nodelay: bool,
+
+    #[arg(long, help = "Set TCP_QUICKACK after every receive, so that ACKs aren't delayed")]
+    quickack: bool,
+
+    #[arg(long, help = "SO_RCVBUF in bytes (default: autotuned by the kernel)")]
+    rcvbuf: Option<u32>,
+
+    #[arg(long, help = "SO_SNDBUF in bytes (default: autotuned by the kernel)")]
+    sndbuf: Option<u32>,
+
+    #[arg(long, help = "Set SO_BUSY_POLL, so that blocking receives busy poll for N us")]
+    busy_poll_us: Option<u32>,
+
+    #[arg(long, help = "Set TCP_CORK while each message is written")]
+    cork: bool,
+
+    #[arg(long, help = "Set SO_REUSEPORT")]
+    reuseport: bool,
+}
+
+impl SocketOpt {
+    fn options(&self) -> SocketOptions {
+        SocketOptions {
+            nodelay: self.nodelay,
+            quickack: self.quickack,
+            rcvbuf: self.rcvbuf,
+            sndbuf: self.sndbuf,
+            busy_poll: self.busy_poll_us,
+            cork: self.cork,
+            reuseport: self.reuseport,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run one measurement per load point and summarize them in `<outpath>/sweep.csv`.
+    ///
+    /// Each point runs for `--runtime-secs`. Rate sweeps use `--num-threads` connections, or
+    /// `--connections` if given.
+    Sweep(SweepOpt),
+    /// Binary search the open loop rate for the highest throughput that meets a latency SLO.
+    ///
+    /// Each probe runs `--repetitions` times for `--runtime-secs` over `--num-threads` connections,
+    /// or `--connections` if given.
+    Capacity(CapacityOpt),
+}
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("steps").required(true)))]
+struct SweepOpt {
+    #[arg(long, group = "steps", help = "Open loop total rates in req/s: a,b,c or start:end:step")]
+    rates: Option<Steps>,
+
+    #[arg(long, group = "steps", help = "Closed loop thread counts: a,b,c or start:end:step")]
+    threads: Option<Steps>,
+
+    #[arg(long, default_value_t = 0, help = "Discarded warmup run before each point")]
+    warmup_secs: u64,
+
+    #[arg(long, default_value_t = 1, help = "Pause after each point so the server can drain")]
+    settle_secs: u64,
+}
+
+#[derive(Args, Debug)]
+struct CapacityOpt {
+    #[arg(long, help = "Latency objective as [p50|p95|p99]:[target_us], e.g. p99:500")]
+    slo: Slo,
+
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), help = "Lowest total rate in req/s")]
+    min_rate: u64,
+
+    #[arg(long, help = "Highest total rate in req/s")]
+    max_rate: u64,
+
+    #[arg(
+        long,
+        default_value_t = 1000,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Stop when the search window is this narrow (req/s)"
+    )]
+    resolution: u64,
+
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..))]
+    repetitions: u64,
+
+    #[arg(long, default_value_t = 0, help = "Discarded warmup run before each probed rate")]
+    warmup_secs: u64,
+
+    #[arg(long, default_value_t = 1, help = "Pause after each run so the server can drain")]
+    settle_secs: u64,
 }
 
 fn main() {
     let opt = Opt::parse();
-    let server_addr = SocketAddrV4::new(opt.ip, opt.port);
+    let outpath = opt.outpath.clone();
+    let mut manifest = Manifest::new();
+    manifest.add_socket_options("requested", &opt.sockets.options());
+    if let Some(cpus) = opt.client_cpus.clone() {
+        let placement = match opt.cpu_placement {
+            CpuPlacement::shared => Placement::Shared,
+            CpuPlacement::spread => Placement::Spread,
+        };
+        manifest.add("client_cpus", &cpus);
+        manifest.add("cpu_placement", placement);
+        if let Err(e) = affinity::pin(cpus, placement) {
+            Opt::command()
+                .error(ErrorKind::InvalidValue, format!("cannot pin to --client-cpus: {}", e))
+                .exit();
+        }
+    }
+    run(opt, &mut manifest);
+    if let Some(applied) = socket::applied() {
+        manifest.add_socket_options("applied", &applied);
+    }
+    if let Err(e) = manifest.write(&outpath, "manifest") {
+        eprintln!("Failed to write run manifest: {:?}", e);
+    }
+}
+
+fn run(opt: Opt, manifest: &mut Manifest) {
+    let servers = match (&opt.ip, opt.port) {
+        (Some(ip), Some(port)) => match socket::resolve(ip, port) {
+            Ok(addr) => Servers::single(addr),
+            Err(e) => Opt::command()
+                .error(ErrorKind::InvalidValue, format!("cannot resolve --ip {}: {}", ip, e))
+                .exit(),
+        },
+        _ => Servers {
+            addrs: opt.servers.clone(),
+            policy: match opt.balance {
+                BalancePolicy::rr => Policy::RoundRobin,
+                BalancePolicy::random => Policy::Random,
+                BalancePolicy::p2c => Policy::PowerOfTwo,
+                BalancePolicy::hash => Policy::ConsistentHash,
+            },
+            granularity: match opt.balance_per {
+                BalanceGranularity::connection => Granularity::Connection,
+                BalanceGranularity::request => Granularity::Request,
+            },
+        },
+    };
+    match servers.addrs.as_slice() {
+        [addr] => manifest.add("server_addr", addr),
+        addrs => {
+            let addrs: Vec<_> = addrs.iter().map(SocketAddr::to_string).collect();
+            manifest.add("servers", addrs.join(","));
+            manifest.add("balance", servers.policy);
+            manifest.add("balance_per", servers.granularity);
+        }
+    }
     let runtime = Duration::from_secs(opt.runtime_secs);
     let outpath = opt.outpath.clone();
-    if let Some(interarrival) = opt.interval_us {
+    let backend = match (opt.backend, opt.connections) {
+        (Some(backend), _) => backend,
+        (None, Some(_)) => ClientBackend::epoll,
+        (None, None) => ClientBackend::threads,
+    };
+    if backend != ClientBackend::uring && (opt.sqpoll || opt.registered_buffers) {
+        Opt::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--sqpoll and --registered-buffers need --backend uring",
+            )
+            .exit();
+    }
+    if backend == ClientBackend::threads && opt.connections.is_some_and(|c| c != opt.num_threads) {
+        Opt::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "the threads backend uses one connection per thread; use --backend epoll or uring",
+            )
+            .exit();
+    }
+    let clients = Clients {
+        threads: opt.num_threads as _,
+        connections: opt.connections.unwrap_or(opt.num_threads) as _,
+        backend: match backend {
+            ClientBackend::threads => Backend::Threads,
+            ClientBackend::epoll => Backend::Epoll,
+            ClientBackend::uring => Backend::Uring(UringConfig {
+                sqpoll: opt.sqpoll,
+                registered_buffers: opt.registered_buffers,
+            }),
+        },
+        sockets: opt.sockets.options(),
+    };
+    let measurement = MeasurementConfig {
+        window: MeasurementWindow {
+            warmup: Duration::from_millis(opt.warmup_ms),
+            cooldown: Duration::from_millis(opt.cooldown_ms),
+        },
+        sample_interval: opt.sample_interval_ms.map(Duration::from_millis),
+        live: opt.live,
+        record_trace: opt.record_trace,
+        tcp_info_interval: opt.tcp_info_ms.map(Duration::from_millis),
+    };
+
+    if let Some(path) = opt.trace {
+        if opt.command.is_some() {
+            Opt::command()
+                .error(ErrorKind::ArgumentConflict, "--trace cannot be used with a subcommand")
+                .exit();
+        }
+        let trace = Trace::load(&path).expect("failed to load trace");
+        open_loop_client::replay(
+            &servers,
+            clients,
+            &trace,
+            runtime,
+            measurement,
+            outpath,
+        );
+        return;
+    }
+    let work = opt.work.expect("clap requires --work without --trace");
+    let users = UserModel {
+        think: opt.think,
+        session_requests: opt.session_requests,
+        session_gap: opt.session_gap,
+    };
+    let closed_loop_sweep = matches!(
+        &opt.command,
+        Some(Command::Sweep(SweepOpt {
+            threads: Some(_),
+            ..
+        }))
+    );
+    if users != UserModel::default() && opt.command.is_some() && !closed_loop_sweep {
+        Opt::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--think and --session-requests only apply to closed loop runs and thread sweeps",
+            )
+            .exit();
+    }
+
+    let profile = match (opt.profile, opt.profile_file) {
+        (Some(shape), _) => Some(LoadProfile::single(shape)),
+        (None, Some(path)) => Some(LoadProfile::load(&path).expect("failed to load profile")),
+        (None, None) => None,
+    };
+    if let Some(profile) = profile {
+        if opt.command.is_some() {
+            Opt::command()
+                .error(ErrorKind::ArgumentConflict, "load profiles cannot be used with a subcommand")
+                .exit();
+        }
+        open_loop_client::run_profile(
+            &servers,
+            clients,
+            &profile,
+            runtime,
+            work,
+            measurement,
+            outpath,
+        );
+        return;
+    }
+
+    if let Some(Command::Capacity(capacity_opt)) = opt.command {
+        let config = SearchConfig {
+            slo: capacity_opt.slo,
+            min_rate: capacity_opt.min_rate,
+            max_rate: capacity_opt.max_rate,
+            resolution: capacity_opt.resolution,
+            repetitions: capacity_opt.repetitions as _,
+            measurement,
+            warmup: Duration::from_secs(capacity_opt.warmup_secs),
+            settle: Duration::from_secs(capacity_opt.settle_secs),
+        };
+        capacity::search(
+            &servers,
+            clients,
+            &config,
+            runtime,
+            work,
+            outpath,
+        )
+        .expect("capacity search failed");
+        return;
+    }
+
+    if let Some(Command::Sweep(sweep_opt)) = opt.command {
+        let kind = match (sweep_opt.rates, sweep_opt.threads) {
+            (Some(Steps(rates)), _) => SweepKind::Rates {
+                rates,
+                clients,
+            },
+            (None, Some(Steps(threads))) => SweepKind::Threads {
+                threads: threads.into_iter().map(|t| t as _).collect(),
+                clients,
+                users,
+            },
+            (None, None) => unreachable!("clap requires one of --rates or --threads"),
+        };
+        sweep::run(
+            &servers,
+            kind,
+            runtime,
+            measurement,
+            Pacing {
+                warmup: Duration::from_secs(sweep_opt.warmup_secs),
+                settle: Duration::from_secs(sweep_opt.settle_secs),
+            },
+            work,
+            outpath,
+        )
+        .expect("sweep failed");
+        return;
+    }
+
+    let interarrival = match (opt.interval_us, opt.rate) {
+        (Some(interval_us), _) => Some(Duration::from_micros(interval_us)),
+        (None, Some(rate)) => Some(open_loop_client::interarrival_for_rate(
+            rate,
+            clients.connections,
+        )),
+        (None, None) => None,
+    };
+    if let Some(interarrival) = interarrival {
         open_loop_client::run(
-            server_addr,
-            opt.num_threads as _,
-            Duration::from_micros(interarrival),
+            &servers,
+            clients,
+            interarrival,
             runtime,
-            opt.work,
+            work,
+            measurement,
             outpath,
         );
     } else {
         closed_loop_client::run(
-            server_addr,
-            opt.num_threads as _,
+            &servers,
+            clients,
             runtime,
-            opt.work,
+            work,
+            users,
+            measurement,
             outpath,
         );
     }
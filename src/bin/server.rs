@@ -1,46 +1,226 @@
 //! Server logic for the CS1675 network APIs project.
 
-use clap::{Parser, ValueEnum};
-use netapis_s25_dev::tcp_server::tcp_server;
+use clap::{error::ErrorKind, ArgAction, CommandFactory, Parser, ValueEnum};
+use netapis_s25_dev::{
+    affinity::{self, CpuList, Placement},
+    app, fanout,
+    manifest::Manifest,
+    socket::{self, SocketOptions},
+    tcp_info,
+    tcp_server::{reuseport_server, tcp_server, ServerOptions},
+};
 
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::time::Duration;
 
 #[derive(Copy, Clone, Debug, ValueEnum, Eq, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum ServerKind {
     tcp,
+    reuseport,
 }
 
 impl ServerKind {
     pub fn as_string_arg(&self) -> String {
         match self {
             Self::tcp => "tcp",
+            Self::reuseport => "reuseport",
         }
         .into()
     }
 }
 
+#[derive(Copy, Clone, Debug, ValueEnum, Eq, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum CpuPlacement {
+    shared,
+    spread,
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about=None)]
 struct Args {
     #[arg(short, long)]
     port: u16,
 
-    #[arg(short, long)]
+    #[arg(
+        long,
+        help = "Address to listen on (default: all interfaces, IPv4 and IPv6 where available)"
+    )]
+    bind: Option<IpAddr>,
+
+    #[arg(
+        short,
+        long,
+        help = "tcp accepts on one listener; reuseport accepts on --listeners SO_REUSEPORT listeners, each pinned to a CPU"
+    )]
     kind: ServerKind,
 
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Listeners for the reuseport server (default: one per server CPU)"
+    )]
+    listeners: Option<u64>,
+
     #[arg(short, long)]
     runtime_secs: u64,
+
+    #[arg(
+        long,
+        help = "Directory for the fileread work scratch file (default: temp dir)"
+    )]
+    scratch_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = socket::resolve_host_port,
+        help = "Backend servers (host:port,...) that fanout work sends sub-requests to"
+    )]
+    backends: Vec<SocketAddr>,
+
+    #[arg(long, help = "Sample each connection's TCP_INFO every N ms")]
+    tcp_info_ms: Option<u64>,
+
+    #[arg(long, help = "Pin server threads to these CPUs, e.g. 0-3 or 0,2")]
+    server_cpus: Option<CpuList>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = CpuPlacement::shared,
+        requires = "server_cpus",
+        help = "Let pinned threads share all of the CPUs, or spread connection threads one per CPU round robin"
+    )]
+    cpu_placement: CpuPlacement,
+
+    #[arg(
+        long,
+        default_value = ".",
+        help = "Directory for the server_manifest.txt and server_tcp_info.csv written at exit"
+    )]
+    outpath: PathBuf,
+
+    #[command(flatten)]
+    sockets: SocketOpt,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "Socket options")]
+struct SocketOpt {
+    #[arg(
+        long,
+        default_value_t = true,
+        action = ArgAction::Set,
+        help = "Set TCP_NODELAY, so that Nagle's algorithm doesn't hold back small writes"
+    )]
+    nodelay: bool,
+
+    #[arg(long, help = "Set TCP_QUICKACK after every receive, so that ACKs aren't delayed")]
+    quickack: bool,
+
+    #[arg(long, help = "SO_RCVBUF in bytes (default: autotuned by the kernel)")]
+    rcvbuf: Option<u32>,
+
+    #[arg(long, help = "SO_SNDBUF in bytes (default: autotuned by the kernel)")]
+    sndbuf: Option<u32>,
+
+    #[arg(long, help = "Set SO_BUSY_POLL, so that blocking receives busy poll for N us")]
+    busy_poll_us: Option<u32>,
+
+    #[arg(long, help = "Set TCP_CORK while each response is written")]
+    cork: bool,
+
+    #[arg(long, help = "Set SO_REUSEPORT on the listening socket and its connections")]
+    reuseport: bool,
+}
+
+impl SocketOpt {
+    fn options(&self) -> SocketOptions {
+        SocketOptions {
+            nodelay: self.nodelay,
+            quickack: self.quickack,
+            rcvbuf: self.rcvbuf,
+            sndbuf: self.sndbuf,
+            busy_poll: self.busy_poll_us,
+            cork: self.cork,
+            reuseport: self.reuseport,
+        }
+    }
 }
 
 fn main() {
     let args = Args::parse();
-    let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, args.port);
+    let mut manifest = Manifest::new();
+    manifest.add_socket_options("requested", &args.sockets.options());
+    // Pinned before calibration, so that busy work is calibrated on the CPUs that will run it.
+    if let Some(cpus) = args.server_cpus.clone() {
+        let placement = match args.cpu_placement {
+            CpuPlacement::shared => Placement::Shared,
+            CpuPlacement::spread => Placement::Spread,
+        };
+        manifest.add("server_cpus", &cpus);
+        manifest.add("cpu_placement", placement);
+        if let Err(e) = affinity::pin(cpus, placement) {
+            Args::command()
+                .error(ErrorKind::InvalidValue, format!("cannot pin to --server-cpus: {}", e))
+                .exit();
+        }
+    }
+    let listeners = match (args.kind, args.listeners) {
+        (ServerKind::reuseport, Some(n)) => n as usize,
+        (ServerKind::reuseport, None) => affinity::allowed_cpus().map_or(1, |cpus| cpus.len()),
+        (ServerKind::tcp, None) => 1,
+        (ServerKind::tcp, Some(_)) => Args::command()
+            .error(ErrorKind::ArgumentConflict, "--listeners needs --kind reuseport")
+            .exit(),
+    };
+    manifest.add("kind", args.kind.as_string_arg());
+    manifest.add("listeners", listeners);
+    let addr = match args.bind {
+        Some(ip) => SocketAddr::new(ip, args.port),
+        None => socket::any_addr(args.port),
+    };
+    manifest.add("addr", addr);
     let runtime_secs = args.runtime_secs;
+    if let Some(dir) = args.scratch_dir {
+        app::set_scratch_dir(dir);
+    }
+    if let Err(e) = app::create_scratch_file() {
+        eprintln!("Warning: fileread work will fail: {:?}", e);
+    }
+    fanout::set_backends(args.backends, args.sockets.options());
+    println!(
+        "Busy work calibration: {:.2} iterations/us",
+        app::calibrate_busy_work()
+    );
 
-    std::thread::spawn(move || match args.kind {
-        ServerKind::tcp => tcp_server(addr),
+    let options = ServerOptions {
+        sockets: args.sockets.options(),
+        tcp_info_interval: args.tcp_info_ms.map(Duration::from_millis),
+        ..Default::default()
+    };
+    let tcp_info = options.tcp_info.clone();
+    std::thread::spawn(move || {
+        let result = match args.kind {
+            ServerKind::tcp => tcp_server(addr, options),
+            ServerKind::reuseport => reuseport_server(addr, listeners, options),
+        };
+        if let Err(e) = result {
+            eprintln!("Server failed: {:?}", e);
+        }
     });
     std::thread::sleep(Duration::from_secs(runtime_secs + 1));
+
+    if let Some(applied) = socket::applied() {
+        manifest.add_socket_options("applied", &applied);
+    }
+    if let Err(e) = manifest.write(&args.outpath, "server_manifest") {
+        eprintln!("Failed to write run manifest: {:?}", e);
+    }
+    if let Err(e) = tcp_info::report(&args.outpath, "server_tcp_info", &tcp_info.take()) {
+        eprintln!("Failed to write TCP info samples: {:?}", e);
+    }
 }
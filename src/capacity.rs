@@ -0,0 +1,321 @@
+//! Search for the highest open loop request rate that still meets a latency SLO.
+
+use crate::{
+    app::WorkMix,
+    balance::Servers,
+    engine::Clients,
+    metrics::{mean_ci95, MeasurementConfig, Percentiles},
+    open_loop_client,
+};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    num::ParseIntError,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// Which latency percentile an [`Slo`] constrains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SloPercentile {
+    P50,
+    P95,
+    P99,
+}
+
+impl SloPercentile {
+    fn select(self, latency: &Percentiles) -> f64 {
+        match self {
+            Self::P50 => latency.p50,
+            Self::P95 => latency.p95,
+            Self::P99 => latency.p99,
+        }
+    }
+}
+
+/// A latency service-level objective: the chosen percentile must stay below `target_us`.
+///
+/// Implements [`FromStr`](std::str::FromStr). String format is `[p50|p95|p99]:[target_us]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slo {
+    pub percentile: SloPercentile,
+    pub target_us: u64,
+}
+
+impl std::str::FromStr for Slo {
+    type Err = SloParseErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sp: Vec<_> = s.split(':').collect();
+        let (percentile, target) = match &sp[..] {
+            [p, target] if *p == "p50" => (SloPercentile::P50, target),
+            [p, target] if *p == "p95" => (SloPercentile::P95, target),
+            [p, target] if *p == "p99" => (SloPercentile::P99, target),
+            _ => return Err(SloParseErr::UnknownFmt(s.to_owned())),
+        };
+        Ok(Slo {
+            percentile,
+            target_us: target.parse()?,
+        })
+    }
+}
+
+impl std::fmt::Display for Slo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let p = match self.percentile {
+            SloPercentile::P50 => "p50",
+            SloPercentile::P95 => "p95",
+            SloPercentile::P99 => "p99",
+        };
+        write!(f, "{}:{}", p, self.target_us)
+    }
+}
+
+/// Things that can go wrong when parsing an [`Slo`].
+#[derive(Debug)]
+pub enum SloParseErr {
+    /// The `percentile:target` format wasn't followed.
+    UnknownFmt(String),
+    /// Followed `percentile:target`, but `target` wasn't a `u64`.
+    U64Parse(ParseIntError),
+}
+
+impl From<ParseIntError> for SloParseErr {
+    fn from(value: ParseIntError) -> Self {
+        Self::U64Parse(value)
+    }
+}
+
+impl std::fmt::Display for SloParseErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownFmt(s) => {
+                write!(f, "Unknown SLO specification {}. Format is [p50|p95|p99]:[target_us].", s)
+            }
+            Self::U64Parse(n) => write!(f, "Could not parse SLO target {} as u64.", n),
+        }
+    }
+}
+
+impl std::error::Error for SloParseErr {}
+
+/// Parameters of a capacity search.
+#[derive(Debug, Clone)]
+pub struct SearchConfig {
+    pub slo: Slo,
+    /// Lowest total rate (req/s) to consider. The search fails if this rate misses the SLO.
+    pub min_rate: u64,
+    /// Highest total rate (req/s) to consider.
+    pub max_rate: u64,
+    /// Stop once the passing and failing rates are this close (req/s). Treated as 1 if 0, since
+    /// the search can't narrow further.
+    pub resolution: u64,
+    /// Runs per probed rate. A rate passes if the mean of the chosen percentile meets the SLO.
+    pub repetitions: usize,
+    /// How each run is measured.
+    pub measurement: MeasurementConfig,
+    /// Discarded run before each probed rate.
+    pub warmup: Duration,
+    /// Pause after each run so the server can drain.
+    pub settle: Duration,
+}
+
+/// The measurements taken at one probed rate.
+#[derive(Debug, Clone)]
+pub struct Probe {
+    pub rate: u64,
+    /// Achieved load of each repetition, in req/s.
+    pub achieved: Vec<f64>,
+    /// The SLO percentile of each repetition, in us. Infinite if a run recorded no latencies.
+    pub latency: Vec<f64>,
+    pub passed: bool,
+    /// Whether the client couldn't keep up in any repetition.
+    pub client_limited: bool,
+}
+
+/// The highest probed rate that met the SLO.
+#[derive(Debug, Clone, Copy)]
+pub struct Capacity {
+    pub offered_rate: u64,
+    /// Mean achieved load across repetitions at `offered_rate`, in req/s.
+    pub achieved_mean: f64,
+    /// Half-width of the 95% confidence interval around `achieved_mean`.
+    pub achieved_ci95: f64,
+    /// Mean of the SLO percentile across repetitions, in us.
+    pub latency_mean: f64,
+}
+
+/// Binary search the open loop rate for the highest throughput that meets `config.slo`.
+///
+/// Every probe is logged to `outdir/capacity.csv`, and its raw records to `outdir/<rate>-<rep>/`.
+/// Returns `None` if even `config.min_rate` misses the SLO.
+pub fn search(
+    servers: &Servers,
+    clients: Clients,
+    config: &SearchConfig,
+    runtime: Duration,
+    work: WorkMix,
+    outdir: PathBuf,
+) -> Result<Option<Capacity>, anyhow::Error> {
+    if config.min_rate == 0 || config.min_rate >= config.max_rate {
+        anyhow::bail!(
+            "Capacity search needs 0 < min rate < max rate, got {} and {}",
+            config.min_rate,
+            config.max_rate
+        );
+    }
+    fs::create_dir_all(&outdir)?;
+    let mut probes = Vec::new();
+    let mut probe = |rate: u64| {
+        let p = probe_rate(servers, clients, config, rate, runtime, &work, &outdir);
+        println!(
+            "\nProbe {} req/s: {} = {:.1} us ({}{})",
+            rate,
+            config.slo,
+            p.latency.iter().sum::<f64>() / p.latency.len() as f64,
+            if p.passed { "pass" } else { "fail" },
+            if p.client_limited { ", client limited" } else { "" }
+        );
+        probes.push(p.clone());
+        p
+    };
+
+    let mut best = probe(config.min_rate);
+    if best.passed {
+        let (mut lo, mut hi) = (config.min_rate, config.max_rate);
+        let top = probe(hi);
+        if top.passed {
+            best = top;
+            lo = hi;
+        }
+        while hi - lo > config.resolution.max(1) {
+            let mid = lo + (hi - lo) / 2;
+            let p = probe(mid);
+            if p.passed {
+                lo = mid;
+                best = p;
+            } else {
+                hi = mid;
+            }
+        }
+    }
+
+    write_probes(&outdir, &probes)?;
+    if probes.iter().any(|p| p.client_limited && !p.passed) {
+        println!(
+            "\nWARNING: the client could not keep up at a failing rate, so the SLO may have been \
+             missed because of the client rather than the server."
+        );
+    }
+    if !best.passed {
+        println!("\nNo rate at or above {} req/s meets {}", config.min_rate, config.slo);
+        return Ok(None);
+    }
+
+    let (achieved_mean, achieved_ci95) = mean_ci95(&best.achieved);
+    let capacity = Capacity {
+        offered_rate: best.rate,
+        achieved_mean,
+        achieved_ci95,
+        latency_mean: best.latency.iter().sum::<f64>() / best.latency.len() as f64,
+    };
+    println!("\nCapacity at {}:", config.slo);
+    println!("Offered load: {} req/s", capacity.offered_rate);
+    println!(
+        "Achieved load: {:.2} +/- {:.2} req/s (95% CI, n = {})",
+        capacity.achieved_mean,
+        capacity.achieved_ci95,
+        best.achieved.len()
+    );
+    println!("Latency: {:.1} us", capacity.latency_mean);
+    Ok(Some(capacity))
+}
+
+fn probe_rate(
+    servers: &Servers,
+    clients: Clients,
+    config: &SearchConfig,
+    rate: u64,
+    runtime: Duration,
+    work: &WorkMix,
+    outdir: &Path,
+) -> Probe {
+    let interarrival = open_loop_client::interarrival_for_rate(rate, clients.connections);
+    let run = |runtime, rep: usize| {
+        let summary = open_loop_client::run(
+            servers,
+            clients,
+            interarrival,
+            runtime,
+            work.clone(),
+            config.measurement,
+            outdir.join(format!("{}-{}", rate, rep)),
+        );
+        thread::sleep(config.settle);
+        summary
+    };
+
+    if !config.warmup.is_zero() {
+        run(config.warmup, 0);
+    }
+    let (mut achieved, mut latency) = (Vec::new(), Vec::new());
+    let mut client_limited = false;
+    for rep in 1..=config.repetitions {
+        let summary = run(runtime, rep);
+        achieved.push(summary.achieved_load);
+        client_limited |= summary.generator.bottleneck;
+        latency.push(
+            summary
+                .latency
+                .map_or(f64::INFINITY, |l| config.slo.percentile.select(&l)),
+        );
+    }
+    let mean = latency.iter().sum::<f64>() / latency.len() as f64;
+    Probe {
+        rate,
+        achieved,
+        latency,
+        passed: mean < config.slo.target_us as f64,
+        client_limited,
+    }
+}
+
+fn write_probes(outdir: &Path, probes: &[Probe]) -> Result<(), anyhow::Error> {
+    let mut out = BufWriter::new(File::create(outdir.join("capacity.csv"))?);
+    writeln!(out, "offered_rps,repetition,achieved_rps,latency_us,passed,client_limited")?;
+    for p in probes {
+        for (rep, (achieved, latency)) in p.achieved.iter().zip(&p.latency).enumerate() {
+            writeln!(
+                out,
+                "{},{},{:.2},{:.1},{},{}",
+                p.rate,
+                rep + 1,
+                achieved,
+                latency,
+                p.passed,
+                p.client_limited
+            )?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::{Slo, SloParseErr, SloPercentile};
+
+    #[test]
+    fn parse_slo() {
+        assert_eq!(
+            "p99:500".parse::<Slo>().expect("parse slo"),
+            Slo {
+                percentile: SloPercentile::P99,
+                target_us: 500
+            }
+        );
+        assert!(matches!("p90:500".parse::<Slo>(), Err(SloParseErr::UnknownFmt(_))));
+        assert!(matches!("p99".parse::<Slo>(), Err(SloParseErr::UnknownFmt(_))));
+        assert!(matches!("p99:foo".parse::<Slo>(), Err(SloParseErr::U64Parse(_))));
+    }
+}
@@ -10,7 +10,7 @@ pub struct ChunkedTcpStream(TcpStream);
 impl ChunkedTcpStream {
     pub fn send_msg_chunk(&mut self, bytes: &[u8]) -> Result<(), anyhow::Error> {
         assert!(bytes.len() <= MSG_SIZE_BYTES);
-        self.0.write_all(&bytes)?;
+        self.0.write_all(bytes)?;
         self.0.flush()?;
         Ok(())
     }
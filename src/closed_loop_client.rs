@@ -1,14 +1,24 @@
 use crate::{
-    app::Work,
+    affinity,
+    app::{Work, WorkMix, WorkParseErr},
+    balance::{Balancer, Servers},
+    engine::{self, Backend, Clients, Next, Requests},
+    generator::{self, ProcessMonitor, Role, ThreadMonitor, ThreadUsage},
     get_current_time_micros,
+    metrics::{
+        report_backends, report_classes, report_fanout, write_latencies, MeasurementConfig,
+        Percentiles, RunSummary,
+    },
     protocol::{work_request::ClientWorkPacketConn, work_response::ServerWorkPacketConn},
     serialize::{ClientWorkPacket, LatencyRecord},
+    tcp_info::{self, TcpInfoSample, TcpInfoSampler},
+    timeseries::{IntervalRecorder, Sampler},
 };
 use std::{
-    fs::File,
-    io::{BufWriter, Write},
-    net::{SocketAddrV4, TcpStream},
+    io,
+    num::NonZeroU64,
     path::PathBuf,
+    sync::{atomic::Ordering, Arc},
     thread::{self, JoinHandle},
     time::{Duration, Instant},
 };
@@ -16,14 +26,20 @@ use std::{
 // Simple struct to track attempted load
 struct AttemptedLoadTracker {
     request_count: usize,
+    error_count: usize,
     start_time: Instant,
+    server_iters_per_us: Option<f64>,
+    session_count: usize,
 }
 
 impl AttemptedLoadTracker {
     fn new() -> Self {
         AttemptedLoadTracker {
             request_count: 0,
+            error_count: 0,
             start_time: Instant::now(),
+            server_iters_per_us: None,
+            session_count: 0,
         }
     }
 
@@ -31,6 +47,10 @@ impl AttemptedLoadTracker {
         self.request_count += 1;
     }
 
+    fn record_error(&mut self) {
+        self.error_count += 1;
+    }
+
     fn get_attempted_load(&self) -> f64 {
         let elapsed_secs = self.start_time.elapsed().as_secs_f64();
         if elapsed_secs > 0.0 {
@@ -41,101 +61,440 @@ impl AttemptedLoadTracker {
     }
 }
 
-fn client_worker(server_addr: SocketAddrV4, runtime: Duration, work: Work) -> (Vec<LatencyRecord>, AttemptedLoadTracker) {
-    let stream = TcpStream::connect(&server_addr).expect("Failed to connect to server");
-    let mut client_conn = ClientWorkPacketConn::new(&stream);
-    let mut server_conn = ServerWorkPacketConn::new(&stream);
+/// Idle time a closed loop user spends between requests or sessions.
+///
+/// Implements [`FromStr`](std::str::FromStr) using the [`Work`] format. Only variants specified
+/// in time are allowed, e.g. `const:[us]`, `exp:[mean]` or `lognormal:[mean],[sigma]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThinkTime(Work);
+
+impl ThinkTime {
+    pub fn sample(&self) -> Duration {
+        self.0.sample_duration().unwrap_or_default()
+    }
+}
 
-    let mut latencies = Vec::new();
-    let mut load_tracker = AttemptedLoadTracker::new();
+impl std::str::FromStr for ThinkTime {
+    type Err = WorkParseErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let work: Work = s.parse()?;
+        match work.sample_duration() {
+            Some(_) => Ok(Self(work)),
+            None => Err(WorkParseErr::InvalidParam(
+                "think time must be specified in time, e.g. const:[us] or exp:[mean]",
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ThinkTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// What one closed loop thread measured.
+struct WorkerOutput {
+    latencies: Vec<LatencyRecord>,
+    load_tracker: AttemptedLoadTracker,
+    usage: ThreadUsage,
+    tcp_info: Vec<TcpInfoSample>,
+}
+
+/// How each closed loop thread behaves as an interactive user.
+///
+/// The default sends each request as soon as the previous response arrives, over one
+/// connection for the whole run.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UserModel {
+    /// Idle time after each response before the next request.
+    pub think: Option<ThinkTime>,
+    /// Requests per session. Each session opens its own connection and closes it when done. If
+    /// `None`, one session lasts the whole run.
+    pub session_requests: Option<NonZeroU64>,
+    /// Idle time between the end of one session and the start of the next.
+    pub session_gap: Option<ThinkTime>,
+}
+
+/// How long a threaded closed loop user waits to retry after failing to connect, at first. The
+/// wait doubles with each consecutive failure, up to [`MAX_CONNECT_BACKOFF`].
+const CONNECT_BACKOFF: Duration = Duration::from_millis(10);
+
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+// What a threaded closed loop user accumulates over its sessions
+struct UserRecords {
+    // The user's index, which places its connection and labels its sockets' TCP state samples
+    id: usize,
+    latencies: Vec<LatencyRecord>,
+    load_tracker: AttemptedLoadTracker,
+    tcp_info: TcpInfoSampler,
+}
+
+// Run user `id` until `runtime` has passed
+fn client_worker(
+    balancer: Arc<Balancer>,
+    id: usize,
+    runtime: Duration,
+    work: WorkMix,
+    users: UserModel,
+    recorder: Arc<IntervalRecorder>,
+    tcp_info_interval: Option<Duration>,
+) -> WorkerOutput {
+    let monitor = ThreadMonitor::start(Role::User);
+    let mut records = UserRecords {
+        id,
+        latencies: Vec::new(),
+        load_tracker: AttemptedLoadTracker::new(),
+        tcp_info: TcpInfoSampler::new(tcp_info_interval),
+    };
     let start = Instant::now();
-    while start.elapsed().as_secs() < runtime.as_secs() {
+    let running = || start.elapsed().as_secs() < runtime.as_secs();
+    // Idle time is cut short at the end of the run, so that it doesn't stretch the run.
+    let idle = |amt: Duration| thread::sleep(amt.min(runtime.saturating_sub(start.elapsed())));
+    let mut backoff = CONNECT_BACKOFF;
+    while running() {
+        let session = run_session(&balancer, &work, &users, running, idle, &mut records, &recorder);
+        if let Err(e) = session {
+            eprintln!("Failed to connect to server: {:?}", e);
+            records.load_tracker.record_error();
+            recorder.record_error();
+            idle(backoff);
+            backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+            continue;
+        }
+        backoff = CONNECT_BACKOFF;
+        records.load_tracker.session_count += 1;
+        if let Some(gap) = users.session_gap {
+            idle(gap.sample());
+        }
+    }
+
+    WorkerOutput {
+        latencies: records.latencies,
+        load_tracker: records.load_tracker,
+        usage: monitor.finish(),
+        tcp_info: records.tcp_info.take(),
+    }
+}
+
+/// Connect, send up to `users.session_requests` requests while `running` returns true, each to
+/// the server `balancer` routes it to, and disconnect, thinking between requests with `idle`.
+/// The session ends early if a request fails. Fails only if connecting fails.
+fn run_session(
+    balancer: &Balancer,
+    work: &WorkMix,
+    users: &UserModel,
+    running: impl Fn() -> bool,
+    idle: impl Fn(Duration),
+    records: &mut UserRecords,
+    recorder: &IntervalRecorder,
+) -> io::Result<()> {
+    let UserRecords {
+        id,
+        latencies,
+        load_tracker,
+        tcp_info,
+    } = records;
+    let streams = balancer.connect(*id)?;
+    let mut conns: Vec<_> = streams
+        .iter()
+        .map(|(_, stream)| (ClientWorkPacketConn::new(stream), ServerWorkPacketConn::new(stream)))
+        .collect();
+
+    let mut sent = 0;
+    while running() && users.session_requests.is_none_or(|n| sent < n.get()) {
+        if sent > 0 {
+            if let Some(think) = users.think {
+                idle(think.sample());
+                if !running() {
+                    break;
+                }
+            }
+        }
+        sent += 1;
+
+        let work = work.sample();
+        let k = balancer.route(&work);
         let work_packet = ClientWorkPacket::new(rand::random(), work);
+        let (backend, stream) = &streams[k];
+        let (client_conn, server_conn) = &mut conns[k];
         
         // Record attempt before sending
         load_tracker.record_attempt();
         
         // Send the work packet to the server
+        balancer.sent(*backend);
         if let Err(e) = client_conn.send_work_msg(work_packet) {
             eprintln!("Failed to send work packet: {:?}", e);
-            continue;
+            balancer.completed(*backend, 1);
+            load_tracker.record_error();
+            recorder.record_error();
+            break;
         }
+        recorder.record_sent();
+        balancer.options().message_sent(stream).ok();
         
         // Receive the server's response
-        let server_work_packet = match server_conn.recv_work_msg() {
+        let server_work_packet = server_conn.recv_work_msg();
+        balancer.completed(*backend, 1);
+        let server_work_packet = match server_work_packet {
             Ok(packet) => packet,
             Err(e) => {
                 eprintln!("Failed to receive server work packet: {:?}", e);
-                continue;
+                load_tracker.record_error();
+                recorder.record_error();
+                break;
             }
         };
         
+        balancer.options().message_received(stream).ok();
+
         // Calculate latency
         let recv_timestamp = get_current_time_micros();
-        if let Some(latency_record) = server_work_packet.calculate_latency(recv_timestamp) {
-            latencies.push(latency_record);
+        load_tracker.server_iters_per_us = Some(server_work_packet.busy_work_iters_per_us());
+        match server_work_packet.calculate_latency(recv_timestamp) {
+            Some(mut latency_record) => {
+                latency_record.backend = *backend;
+                recorder.record_received(latency_record.latency);
+                latencies.push(latency_record);
+            }
+            None => {
+                load_tracker.record_error();
+                recorder.record_error();
+            }
+        }
+        if tcp_info.due() {
+            for (k, (_, stream)) in streams.iter().enumerate() {
+                tcp_info.record(*id * streams.len() + k, stream);
+            }
         }
     }
-    
-    (latencies, load_tracker)
+    Ok(())
 }
 
-pub fn init_client(
-    server_addr: SocketAddrV4,
+/// One closed loop user on an [`engine`] connection.
+struct User {
+    work: WorkMix,
+    users: UserModel,
+    start: Instant,
     runtime: Duration,
-    work: Work,
-) -> JoinHandle<(Vec<LatencyRecord>, AttemptedLoadTracker)> {
-    thread::spawn(move || client_worker(server_addr, runtime, work))
+    /// Requests sent in the current session.
+    sent: u64,
 }
 
+impl Requests for User {
+    fn next_action(&mut self, now: minstant::Instant, outstanding: usize) -> Next {
+        if outstanding > 0 {
+            return Next::Wait;
+        }
+        if self.start.elapsed().as_secs() >= self.runtime.as_secs() {
+            return Next::Done;
+        }
+        if self.users.session_requests.is_some_and(|n| self.sent == n.get()) {
+            self.sent = 0;
+            let gap = self.users.session_gap.map_or(Duration::ZERO, |gap| gap.sample());
+            return Next::Reconnect(now + gap);
+        }
+        let think = match self.users.think {
+            Some(think) if self.sent > 0 => think.sample(),
+            _ => Duration::ZERO,
+        };
+        self.sent += 1;
+        Next::Send(now + think, self.work.sample(), None)
+    }
+}
+
+// Run `clients.connections` users on `clients.threads` engine threads
+fn run_engine(
+    balancer: Arc<Balancer>,
+    clients: Clients,
+    runtime: Duration,
+    work: &WorkMix,
+    users: UserModel,
+    recorder: Arc<IntervalRecorder>,
+    measurement: MeasurementConfig,
+) -> Vec<WorkerOutput> {
+    engine::raise_fd_limit();
+    let start = Instant::now();
+    // Users that can't connect are given up on, as they are when they can't reconnect.
+    let mut failed = 0;
+    let conns = (0..clients.connections)
+        .filter_map(|id| {
+            let sockets = match balancer.connect(id) {
+                Ok(sockets) => sockets,
+                Err(e) => {
+                    eprintln!("Failed to connect to server: {:?}", e);
+                    recorder.record_error();
+                    failed += 1;
+                    return None;
+                }
+            };
+            let user = User {
+                work: work.clone(),
+                users,
+                start,
+                runtime,
+                sent: 0,
+            };
+            Some((sockets, Box::new(user) as Box<dyn Requests>))
+        })
+        .collect();
+    let handles = engine::spawn(
+        balancer,
+        clients,
+        conns,
+        minstant::Instant::now(),
+        recorder,
+        // Closed loop runs have no schedule to record.
+        &MeasurementConfig {
+            record_trace: false,
+            ..measurement
+        },
+    );
+    handles
+        .into_iter()
+        .map(|(handle, counters)| {
+            let output = handle.join().unwrap();
+            let load_tracker = AttemptedLoadTracker {
+                request_count: counters.sent.load(Ordering::SeqCst) as usize,
+                error_count: counters.errors.load(Ordering::SeqCst) as usize
+                    + std::mem::take(&mut failed),
+                start_time: start,
+                server_iters_per_us: counters.server_iters_per_us(),
+                session_count: counters.sessions.load(Ordering::SeqCst) as usize,
+            };
+            WorkerOutput {
+                latencies: output.latencies,
+                load_tracker,
+                usage: output.usage,
+                tcp_info: output.tcp_info,
+            }
+        })
+        .collect()
+}
+
+/// Start closed loop user `id` on its own thread, connecting through `balancer` and sampling its
+/// sockets' TCP state every `tcp_info_interval` if set.
+fn init_client(
+    balancer: Arc<Balancer>,
+    id: usize,
+    runtime: Duration,
+    work: WorkMix,
+    users: UserModel,
+    recorder: Arc<IntervalRecorder>,
+    tcp_info_interval: Option<Duration>,
+) -> JoinHandle<WorkerOutput> {
+    thread::spawn(move || {
+        affinity::place_thread();
+        client_worker(balancer, id, runtime, work, users, recorder, tcp_info_interval)
+    })
+}
+
+/// Run `clients` closed loop users, each behaving as `users` and sending `work`, for `runtime`.
 pub fn run(
-    server_addr: SocketAddrV4,
-    num_threads: usize,
+    servers: &Servers,
+    clients: Clients,
     runtime: Duration,
-    work: Work,
+    work: WorkMix,
+    users: UserModel,
+    measurement: MeasurementConfig,
     outdir: PathBuf,
-) {
-    let join_handles: Vec<_> = (0..num_threads)
-        .map(|_| init_client(server_addr, runtime, work))
-        .collect();
+) -> RunSummary {
+    let span = measurement.window.span(get_current_time_micros(), runtime);
+    let sampler = Sampler::start(measurement.sample_interval, measurement.live);
+    let process = ProcessMonitor::start();
+    let balancer = Arc::new(Balancer::new(servers, clients.sockets));
+    let results: Vec<_> = match clients.backend {
+        Backend::Threads => {
+            let join_handles: Vec<_> = (0..clients.threads)
+                .map(|id| {
+                    init_client(
+                        balancer.clone(),
+                        id,
+                        runtime,
+                        work.clone(),
+                        users,
+                        sampler.recorder(),
+                        measurement.tcp_info_interval,
+                    )
+                })
+                .collect();
+            join_handles.into_iter().map(|h| h.join().unwrap()).collect()
+        }
+        _ => run_engine(
+            balancer,
+            clients,
+            runtime,
+            &work,
+            users,
+            sampler.recorder(),
+            measurement,
+        ),
+    };
+    let num_threads = results.len();
+    let process_cpu = process.finish();
 
     // Collect latencies and load metrics
     let mut total_attempts = 0;
+    let mut total_completed = 0;
+    let mut total_errors = 0;
+    let mut total_sessions = 0;
+    let mut server_iters_per_us = None;
     let mut total_runtime_secs = 0.0;
+    let mut total_measured = 0;
     let mut thread_loads = Vec::new();
-    let mut median_latencies = Vec::new();
-    let mut p95_latencies = Vec::new();
-    let mut p99_latencies = Vec::new();
-    
-    // Define warm-up constant to ignore initial records for more accurate measurements
-    const WARM_UP: usize = 50;
+    let mut thread_percentiles = Vec::new();
+    let mut request_latencies = Vec::new();
+    let mut usages = Vec::new();
+    let mut tcp_samples = Vec::new();
 
-    for (i, handle) in join_handles.into_iter().enumerate() {
-        let (thread_latencies, load_tracker) = handle.join().unwrap();
+    for WorkerOutput {
+        latencies: thread_latencies,
+        load_tracker,
+        usage,
+        tcp_info,
+    } in results
+    {
         
         let attempted_load = load_tracker.get_attempted_load();
         thread_loads.push(attempted_load);
         
         // Accumulate metrics
         total_attempts += load_tracker.request_count;
+        total_completed += thread_latencies.len();
+        total_errors += load_tracker.error_count;
+        total_sessions += load_tracker.session_count;
+        server_iters_per_us = server_iters_per_us.or(load_tracker.server_iters_per_us);
         total_runtime_secs += load_tracker.start_time.elapsed().as_secs_f64();
         
-        // Calculate percentile latencies for this thread, ignoring warm-up records
-        if thread_latencies.len() > WARM_UP {
-            let mut latency_values: Vec<u64> = thread_latencies.iter()
-                .skip(WARM_UP)  // Skip the first WARM_UP records
-                .map(|record| record.latency)
-                .collect();
-            latency_values.sort();
-            
-            let median_idx = latency_values.len() / 2;
-            let p95_idx = (latency_values.len() as f64 * 0.95) as usize;
-            let p99_idx = (latency_values.len() as f64 * 0.99) as usize;
-            
-            median_latencies.push(latency_values[median_idx]);
-            p95_latencies.push(latency_values[p95_idx]);
-            p99_latencies.push(latency_values[p99_idx]);
-        }  
+        // Calculate percentile latencies for this thread, ignoring warm-up and cool-down records
+        let mut latency_values: Vec<u64> = thread_latencies.iter()
+            .filter(|record| span.contains(record))
+            .map(|record| record.latency)
+            .collect();
+        total_measured += latency_values.len();
+        thread_percentiles.extend(Percentiles::from_unsorted(&mut latency_values));
+        request_latencies.push(thread_latencies);
+        usages.push(usage);
+        tcp_samples.extend(tcp_info);
+    }
+
+    if let Err(e) = sampler.finish(&outdir) {
+        eprintln!("Failed to write time series: {:?}", e);
+    }
+    if let Err(e) = write_latencies(&outdir, &request_latencies, &span) {
+        eprintln!("Failed to write latency records: {:?}", e);
+    }
+    if let Err(e) = report_classes(&outdir, &work, &request_latencies, &span) {
+        eprintln!("Failed to write per-class latencies: {:?}", e);
+    }
+    report_fanout(&request_latencies, &span);
+    if let Err(e) = report_backends(&outdir, &servers.addrs, &request_latencies, &span) {
+        eprintln!("Failed to write per-backend latencies: {:?}", e);
+    }
+    if let Err(e) = tcp_info::report(&outdir, "tcp_info", &tcp_samples) {
+        eprintln!("Failed to write TCP info samples: {:?}", e);
     }
     
     // Calculate aggregate attempted load
@@ -145,6 +504,11 @@ pub fn run(
     } else { 
         0.0 
     };
+    let aggregate_achieved_load = if span.as_secs_f64() > 0.0 {
+        total_measured as f64 / span.as_secs_f64()
+    } else {
+        0.0
+    };
     
     println!("\nAggregate Metrics:");
     println!("Total attempted requests: {}", total_attempts);
@@ -155,14 +519,59 @@ pub fn run(
              } else { 
                  0.0 
              });
+    println!("Achieved load: {:.2} req/s", aggregate_achieved_load);
+    println!("Errors: {}", total_errors);
+    if users.session_requests.is_some() {
+        println!("Sessions: {}", total_sessions);
+    }
+    if let Some(factor) = server_iters_per_us {
+        println!("Server busy work calibration: {:.2} iterations/us", factor);
+    }
 
     // Output mean aggregated latencies
-    let mean_median_latency = median_latencies.iter().sum::<u64>() as f64 / median_latencies.len() as f64;
-    let mean_p95_latency = p95_latencies.iter().sum::<u64>() as f64 / p95_latencies.len() as f64;
-    let mean_p99_latency = p99_latencies.iter().sum::<u64>() as f64 / p99_latencies.len() as f64;
-
-    println!("\nMean Aggregated Latencies:");
-    println!("Median latency: {:.2} us", mean_median_latency);
-    println!("95th percentile latency: {:.2} us", mean_p95_latency);
-    println!("99th percentile latency: {:.2} us", mean_p99_latency);
+    let latency = Percentiles::mean(&thread_percentiles);
+    if let Some(latency) = latency {
+        println!("\nMean Aggregated Latencies:");
+        println!("Median latency: {:.2} us", latency.p50);
+        println!("95th percentile latency: {:.2} us", latency.p95);
+        println!("99th percentile latency: {:.2} us", latency.p99);
+    }
+    let generator = generator::report(&outdir, &usages, process_cpu, false);
+
+    RunSummary {
+        attempted: total_attempts as u64,
+        completed: total_completed as u64,
+        errors: total_errors as u64,
+        attempted_load: aggregate_attempted_load,
+        achieved_load: aggregate_achieved_load,
+        latency,
+        server_iters_per_us,
+        generator,
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::ThinkTime;
+    use crate::app::WorkParseErr;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_think_time() {
+        let think: ThinkTime = "const:250".parse().expect("parse const think time");
+        assert_eq!(think.sample(), Duration::from_micros(250));
+        assert_eq!(think.to_string(), "const:250");
+
+        let think: ThinkTime = "exp:1000".parse().expect("parse exp think time");
+        assert_eq!(think.to_string(), "exp:1000");
+
+        assert!(matches!(
+            "chase:1024,10".parse::<ThinkTime>(),
+            Err(WorkParseErr::InvalidParam(_))
+        ));
+        assert!(matches!(
+            "get:1".parse::<ThinkTime>(),
+            Err(WorkParseErr::InvalidParam(_))
+        ));
+    }
 }
@@ -0,0 +1,898 @@
+//! Event-driven client engine, in which each thread drives many connections.
+//!
+//! The threaded clients dedicate one or two blocking threads to every connection, so the client
+//! runs out of CPU long before the server runs out of capacity once there are more than a few
+//! dozen connections. Here each thread multiplexes its share of the connections with epoll or
+//! io_uring, so a handful of threads can simulate thousands of concurrent clients.
+
+mod uring;
+
+use crate::{
+    affinity,
+    app::Work,
+    balance::Balancer,
+    generator::{Role, ThreadMonitor, ThreadUsage},
+    get_current_time_micros,
+    metrics::MeasurementConfig,
+    protocol::{decode_msg, encode_msg},
+    serialize::{ClientWorkPacket, LatencyRecord, ServerWorkPacket},
+    socket::SocketOptions,
+    tcp_info::{TcpInfoSample, TcpInfoSampler},
+    timeseries::IntervalRecorder,
+    trace::TraceEntry,
+};
+use minstant::Instant;
+use nix::sys::{
+    epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags, EpollTimeout},
+    resource::{getrlimit, setrlimit, Resource},
+};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+pub use uring::UringConfig;
+
+/// How long to wait for outstanding responses once every connection has stopped sending.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// When the next send is closer than this, poll without blocking rather than risk oversleeping.
+const SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+/// How long to block when no sends are scheduled, e.g. while closed loop users wait for
+/// responses.
+const IDLE_WAIT: Duration = Duration::from_millis(100);
+
+/// What drives a client's connections.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// One blocking connection per thread. Open loop connections use a second thread to receive.
+    #[default]
+    Threads,
+    /// Non-blocking connections multiplexed with epoll.
+    Epoll,
+    /// Connections whose sends and receives are submitted through io_uring.
+    Uring(UringConfig),
+}
+
+/// How many client threads and connections a run uses, what drives them, and how their sockets
+/// are set up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clients {
+    pub threads: usize,
+    /// Connections, spread round robin over the threads. [`Backend::Threads`] needs one per
+    /// thread.
+    pub connections: usize,
+    pub backend: Backend,
+    pub sockets: SocketOptions,
+}
+
+impl Clients {
+    /// One blocking connection per thread.
+    pub fn threads(threads: usize) -> Self {
+        Self {
+            threads,
+            connections: threads,
+            backend: Backend::Threads,
+            sockets: SocketOptions::default(),
+        }
+    }
+
+    /// The same clients on `threads` threads. [`Backend::Threads`] gets a connection for each.
+    pub fn with_threads(self, threads: usize) -> Self {
+        match self.backend {
+            Backend::Threads => Self {
+                threads,
+                connections: threads,
+                ..self
+            },
+            _ => Self { threads, ..self },
+        }
+    }
+}
+
+/// Counters shared by the threads serving a set of connections.
+#[derive(Default)]
+pub(crate) struct Counters {
+    pub(crate) sent: AtomicU64,
+    pub(crate) errors: AtomicU64,
+    pub(crate) sessions: AtomicU64,
+    // f64 bits of the server's busy work calibration, 0 until a response arrives
+    server_iters_per_us: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn record_server_iters_per_us(&self, factor: f64) {
+        self.server_iters_per_us
+            .store(factor.to_bits(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn server_iters_per_us(&self) -> Option<f64> {
+        match self.server_iters_per_us.load(Ordering::Relaxed) {
+            0 => None,
+            bits => Some(f64::from_bits(bits)),
+        }
+    }
+}
+
+/// What a connection does next.
+pub(crate) enum Next {
+    /// Send a request for the work, padded with the given payload bytes, at the given time.
+    Send(Instant, Work, Option<usize>),
+    /// Nothing until a response arrives.
+    Wait,
+    /// Close the connection and open a new one at the given time.
+    Reconnect(Instant),
+    /// Stop sending. The connection is finished once its outstanding responses arrive.
+    Done,
+}
+
+/// Decides what one connection sends, and when.
+pub(crate) trait Requests: Send {
+    /// The connection's next action, given that `outstanding` of its requests await responses.
+    fn next_action(&mut self, now: Instant, outstanding: usize) -> Next;
+}
+
+/// A schedule of requests, sent regardless of responses.
+impl Requests for Box<dyn Iterator<Item = (Instant, Work, Option<usize>)> + Send> {
+    fn next_action(&mut self, _now: Instant, _outstanding: usize) -> Next {
+        match self.next() {
+            Some((send_time, work, payload)) => Next::Send(send_time, work, payload),
+            None => Next::Done,
+        }
+    }
+}
+
+/// What one engine thread's connections sent and received.
+pub(crate) struct ThreadOutput {
+    pub(crate) latencies: Vec<LatencyRecord>,
+    /// Every request sent, if recording was requested.
+    pub(crate) sent: Vec<TraceEntry>,
+    pub(crate) usage: ThreadUsage,
+    pub(crate) tcp_info: Vec<TcpInfoSample>,
+}
+
+/// Raise this process's open file limit to its hard limit, so that it can hold many connections.
+pub(crate) fn raise_fd_limit() {
+    if let Ok((soft, hard)) = getrlimit(Resource::RLIMIT_NOFILE) {
+        if soft < hard {
+            let _ = setrlimit(Resource::RLIMIT_NOFILE, hard, hard);
+        }
+    }
+}
+
+/// A client connection's sockets, as opened by [`Balancer::connect`], each with the index of its
+/// server.
+pub(crate) type Sockets = Vec<(usize, TcpStream)>;
+
+/// Drive already-open `conns` over `clients.threads` threads, dealt round robin, until every
+/// connection is done.
+///
+/// Requests are spread over each connection's sockets, and sessions that reconnect do so, by
+/// `balancer`. Sent requests are recorded if `measurement.record_trace` is set, with arrivals
+/// relative to `run_start`. Sampled TCP state is labelled with each socket's index among the
+/// run's sockets, in the order of `conns`.
+pub(crate) fn spawn(
+    balancer: Arc<Balancer>,
+    clients: Clients,
+    conns: Vec<(Sockets, Box<dyn Requests>)>,
+    run_start: Instant,
+    recorder: Arc<IntervalRecorder>,
+    measurement: &MeasurementConfig,
+) -> Vec<(JoinHandle<ThreadOutput>, Arc<Counters>)> {
+    let mut split: Vec<Vec<_>> = (0..clients.threads).map(|_| Vec::new()).collect();
+    for (i, (sockets, requests)) in conns.into_iter().enumerate() {
+        split[i % clients.threads].push((i, sockets, requests));
+    }
+
+    split
+        .into_iter()
+        .map(|conns| {
+            let counters = Arc::new(Counters::default());
+            let (b, c, r, m) = (balancer.clone(), counters.clone(), recorder.clone(), *measurement);
+            let num_sockets = conns.iter().map(|(_, sockets, _)| sockets.len()).sum();
+            // Drivers are built on their own threads so that they measure those threads' CPU use.
+            let handle = match clients.backend {
+                Backend::Threads => panic!("The threads backend doesn't use the engine"),
+                Backend::Epoll => thread::spawn(move || {
+                    affinity::place_thread();
+                    let io = EpollIo::new(num_sockets, b.options()).expect("Failed to set up epoll");
+                    Driver::new(b, io, conns, run_start, c, r, m).run()
+                }),
+                Backend::Uring(config) => thread::spawn(move || {
+                    affinity::place_thread();
+                    let io = uring::UringIo::new(num_sockets, config, b.options())
+                        .expect("Failed to set up io_uring");
+                    Driver::new(b, io, conns, run_start, c, r, m).run()
+                }),
+            };
+            (handle, counters)
+        })
+        .collect()
+}
+
+/// What an [`Io`] reports about a socket, by its index among the driver's sockets.
+enum IoEvent {
+    /// Bytes were appended to the connection's `inbuf`.
+    Received(usize),
+    /// The peer closed the connection.
+    Closed(usize),
+    Failed(usize, io::Error),
+}
+
+/// How a [`Driver`] moves bytes between its sockets and the kernel.
+trait Io: Send + 'static {
+    /// Start doing IO on socket `i`, which is new.
+    fn open(&mut self, i: usize, stream: &TcpStream) -> io::Result<()>;
+
+    /// Stop doing IO on socket `i`, which is about to be closed or replaced.
+    fn close(&mut self, i: usize, stream: &TcpStream);
+
+    /// Start sending socket `i`'s queued bytes, and tell its socket options when they've all
+    /// been handed to the kernel.
+    fn flush(&mut self, i: usize, conn: &mut Conn) -> io::Result<()>;
+
+    /// Wait up to `timeout` for IO on `conns` to make progress, and report what happened.
+    fn poll(
+        &mut self,
+        conns: &mut [Conn],
+        timeout: Duration,
+        events: &mut Vec<IoEvent>,
+    ) -> io::Result<()>;
+}
+
+/// Non-blocking sockets, written directly and read when epoll says they're ready.
+struct EpollIo {
+    epoll: Epoll,
+    /// Whether epoll is watching each socket for becoming writable.
+    want_write: Vec<bool>,
+    events: Vec<EpollEvent>,
+    sockets: SocketOptions,
+}
+
+impl EpollIo {
+    fn new(num_conns: usize, sockets: SocketOptions) -> io::Result<Self> {
+        Ok(Self {
+            epoll: Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC)?,
+            want_write: vec![false; num_conns],
+            events: vec![EpollEvent::empty(); 1024],
+            sockets,
+        })
+    }
+
+    fn read(i: usize, conn: &mut Conn, events: &mut Vec<IoEvent>) {
+        let mut buf = [0; 4096];
+        let mut received = false;
+        let end = loop {
+            match conn.stream.read(&mut buf) {
+                Ok(0) => break Some(IoEvent::Closed(i)),
+                Ok(n) => {
+                    conn.inbuf.extend_from_slice(&buf[..n]);
+                    received = true;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break None,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Some(IoEvent::Failed(i, e)),
+            }
+        };
+        if received {
+            events.push(IoEvent::Received(i));
+        }
+        events.extend(end);
+    }
+}
+
+impl Io for EpollIo {
+    fn open(&mut self, i: usize, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        self.want_write[i] = false;
+        self.epoll
+            .add(stream, EpollEvent::new(EpollFlags::EPOLLIN, i as u64))?;
+        Ok(())
+    }
+
+    fn close(&mut self, _i: usize, stream: &TcpStream) {
+        let _ = self.epoll.delete(stream);
+    }
+
+    fn flush(&mut self, i: usize, conn: &mut Conn) -> io::Result<()> {
+        while !conn.out.is_empty() {
+            match conn.stream.write(&conn.out) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    conn.out.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let want_write = !conn.out.is_empty();
+        if !want_write {
+            self.sockets.message_sent(&conn.stream)?;
+        }
+        if want_write != self.want_write[i] {
+            self.want_write[i] = want_write;
+            let flags = if want_write {
+                EpollFlags::EPOLLIN | EpollFlags::EPOLLOUT
+            } else {
+                EpollFlags::EPOLLIN
+            };
+            self.epoll
+                .modify(&conn.stream, &mut EpollEvent::new(flags, i as u64))?;
+        }
+        Ok(())
+    }
+
+    fn poll(
+        &mut self,
+        conns: &mut [Conn],
+        timeout: Duration,
+        events: &mut Vec<IoEvent>,
+    ) -> io::Result<()> {
+        // epoll timeouts are in whole milliseconds.
+        let ms = timeout.as_millis().min(u16::MAX as u128) as u16;
+        let n = match self.epoll.wait(&mut self.events, EpollTimeout::from(ms)) {
+            Ok(n) => n,
+            Err(nix::errno::Errno::EINTR) => 0,
+            Err(e) => return Err(e.into()),
+        };
+        for k in 0..n {
+            let event = self.events[k];
+            let i = event.data() as usize;
+            if event.events().contains(EpollFlags::EPOLLOUT) {
+                if let Err(e) = self.flush(i, &mut conns[i]) {
+                    events.push(IoEvent::Failed(i, e));
+                    continue;
+                }
+            }
+            if event
+                .events()
+                .intersects(EpollFlags::EPOLLIN | EpollFlags::EPOLLERR | EpollFlags::EPOLLHUP)
+            {
+                Self::read(i, &mut conns[i], events);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One socket of a [`Client`].
+struct Conn {
+    /// The socket's index among all of the run's sockets.
+    id: usize,
+    stream: TcpStream,
+    /// The index of the client it belongs to, among the driver's clients.
+    client: usize,
+    /// The index of the server it's connected to.
+    backend: usize,
+    /// Requests sent over the socket that await responses.
+    outstanding: usize,
+    /// Bytes not yet handed to the kernel.
+    out: Vec<u8>,
+    /// Bytes of a partially received response.
+    inbuf: Vec<u8>,
+    /// Whether the socket has been given up on, so any further IO on it is ignored.
+    closed: bool,
+}
+
+/// One client connection, which sends over one socket or, if requests are balanced, one to each
+/// server.
+struct Client {
+    /// The connection's index among all of the run's connections.
+    id: usize,
+    /// The indices of its sockets among the driver's, in the order [`Balancer::connect`] opened
+    /// them.
+    conns: Vec<usize>,
+    requests: Box<dyn Requests>,
+    /// An action waiting for its time to come.
+    pending: Option<Next>,
+    outstanding: usize,
+    /// Whether the connection has stopped sending, or failed.
+    done: bool,
+}
+
+/// One engine thread.
+struct Driver<I: Io> {
+    balancer: Arc<Balancer>,
+    // Dropped before the sockets, so that it can still wind down IO on them.
+    io: I,
+    conns: Vec<Conn>,
+    clients: Vec<Client>,
+    /// Clients with a pending action, by when it's due.
+    timers: BinaryHeap<Reverse<(Instant, usize)>>,
+    /// Clients to ask for their next action.
+    ready: Vec<usize>,
+    /// Clients that haven't stopped sending.
+    active: usize,
+    /// Requests awaiting responses, across all clients.
+    outstanding: usize,
+    run_start: Instant,
+    counters: Arc<Counters>,
+    recorder: Arc<IntervalRecorder>,
+    record: bool,
+    latencies: Vec<LatencyRecord>,
+    sent: Vec<TraceEntry>,
+    monitor: ThreadMonitor,
+    tcp_info: TcpInfoSampler,
+}
+
+impl<I: Io> Driver<I> {
+    fn new(
+        balancer: Arc<Balancer>,
+        io: I,
+        clients: Vec<(usize, Sockets, Box<dyn Requests>)>,
+        run_start: Instant,
+        counters: Arc<Counters>,
+        recorder: Arc<IntervalRecorder>,
+        measurement: MeasurementConfig,
+    ) -> Self {
+        let per_conn = balancer.sockets_per_conn();
+        let mut conns = Vec::new();
+        let clients: Vec<_> = clients
+            .into_iter()
+            .enumerate()
+            .map(|(c, (id, sockets, requests))| {
+                let first = conns.len();
+                for (k, (backend, stream)) in sockets.into_iter().enumerate() {
+                    conns.push(Conn {
+                        id: id * per_conn + k,
+                        stream,
+                        client: c,
+                        backend,
+                        outstanding: 0,
+                        out: Vec::new(),
+                        inbuf: Vec::new(),
+                        closed: false,
+                    });
+                }
+                Client {
+                    id,
+                    conns: (first..conns.len()).collect(),
+                    requests,
+                    pending: None,
+                    outstanding: 0,
+                    done: false,
+                }
+            })
+            .collect();
+        let mut driver = Self {
+            balancer,
+            io,
+            ready: (0..clients.len()).collect(),
+            active: clients.len(),
+            conns,
+            clients,
+            timers: BinaryHeap::new(),
+            outstanding: 0,
+            run_start,
+            counters,
+            recorder,
+            record: measurement.record_trace,
+            latencies: Vec::new(),
+            sent: Vec::new(),
+            monitor: ThreadMonitor::start(Role::Engine),
+            tcp_info: TcpInfoSampler::new(measurement.tcp_info_interval),
+        };
+        for i in 0..driver.conns.len() {
+            if let Err(e) = driver.io.open(i, &driver.conns[i].stream) {
+                driver.fail(i, e);
+            }
+        }
+        driver
+    }
+
+    fn run(mut self) -> ThreadOutput {
+        let mut events = Vec::new();
+        let mut drain_deadline = None;
+        loop {
+            let now = Instant::now();
+            while let Some(&Reverse((at, c))) = self.timers.peek() {
+                if at > now {
+                    break;
+                }
+                self.timers.pop();
+                if let Some(action) = self.clients[c].pending.take() {
+                    self.act(c, action);
+                    self.ready.push(c);
+                }
+            }
+            while let Some(c) = self.ready.pop() {
+                self.advance(c, now);
+            }
+            if self.tcp_info.due() {
+                for conn in self.conns.iter().filter(|conn| !conn.closed) {
+                    self.tcp_info.record(conn.id, &conn.stream);
+                }
+            }
+
+            if self.active == 0 {
+                if self.outstanding == 0 {
+                    break;
+                }
+                let deadline = *drain_deadline.get_or_insert(now + DRAIN_TIMEOUT);
+                if now >= deadline {
+                    break;
+                }
+            }
+
+            let wait = match self.timers.peek() {
+                Some(Reverse((at, _))) => at.checked_duration_since(now).unwrap_or_default(),
+                None => IDLE_WAIT,
+            };
+            let timeout = wait.saturating_sub(SPIN_THRESHOLD);
+            if let Err(e) = self.io.poll(&mut self.conns, timeout, &mut events) {
+                // There's no telling which sockets' IO was lost, so give up on every client.
+                eprintln!("Client engine IO failed: {:?}", e);
+                for i in 0..self.conns.len() {
+                    self.fail(i, &e);
+                }
+            }
+            for event in events.drain(..) {
+                match event {
+                    IoEvent::Received(i) => self.receive(i),
+                    IoEvent::Closed(i) => {
+                        let conn = &self.conns[i];
+                        if conn.outstanding > 0 || !self.clients[conn.client].done {
+                            self.fail(i, io::ErrorKind::UnexpectedEof);
+                        } else if !conn.closed {
+                            self.io.close(i, &conn.stream);
+                            self.conns[i].closed = true;
+                        }
+                    }
+                    IoEvent::Failed(i, e) => self.fail(i, e),
+                }
+            }
+        }
+        ThreadOutput {
+            latencies: self.latencies,
+            sent: self.sent,
+            usage: self.monitor.finish(),
+            tcp_info: self.tcp_info.take(),
+        }
+    }
+
+    /// Carry out client `c`'s actions until it has to wait for a timer or a response.
+    fn advance(&mut self, c: usize, now: Instant) {
+        loop {
+            let client = &mut self.clients[c];
+            if client.done || client.pending.is_some() {
+                return;
+            }
+            let action = client.requests.next_action(now, client.outstanding);
+            if let Next::Send(at, ..) | Next::Reconnect(at) = action {
+                if at > now {
+                    client.pending = Some(action);
+                    self.timers.push(Reverse((at, c)));
+                    return;
+                }
+            }
+            match action {
+                Next::Wait => return,
+                Next::Done => {
+                    self.counters.sessions.fetch_add(1, Ordering::SeqCst);
+                    self.finish(c);
+                    return;
+                }
+                action => self.act(c, action),
+            }
+        }
+    }
+
+    /// Send or reconnect now.
+    fn act(&mut self, c: usize, action: Next) {
+        if self.clients[c].done {
+            return;
+        }
+        match action {
+            Next::Send(at, work, payload) => {
+                self.monitor
+                    .record_slip(Instant::now().saturating_duration_since(at));
+                self.send(c, at, work, payload)
+            }
+            Next::Reconnect(_) => self.reconnect(c),
+            Next::Wait | Next::Done => {}
+        }
+    }
+
+    /// Send `work` on client `c`, recording it as having arrived `at`, when it was due.
+    fn send(&mut self, c: usize, at: Instant, work: Work, payload: Option<usize>) {
+        let mut work_packet = ClientWorkPacket::new(get_current_time_micros(), work);
+        if let Some(len) = payload {
+            work_packet = work_packet.with_payload(len);
+        }
+        let i = self.clients[c].conns[self.balancer.route(&work)];
+        let conn = &mut self.conns[i];
+        self.balancer.sent(conn.backend);
+        if let Err(e) = encode_msg(&work_packet, &mut conn.out) {
+            eprintln!("Failed to encode work packet: {:?}", e);
+            self.balancer.completed(conn.backend, 1);
+            self.counters.record_error();
+            self.recorder.record_error();
+            return;
+        }
+        conn.outstanding += 1;
+        self.clients[c].outstanding += 1;
+        self.outstanding += 1;
+        self.counters.sent.fetch_add(1, Ordering::SeqCst);
+        self.recorder.record_sent();
+        if self.record {
+            self.sent.push(TraceEntry {
+                arrival: at.saturating_duration_since(self.run_start),
+                work,
+                payload,
+            });
+        }
+        if let Err(e) = self.io.flush(i, conn) {
+            self.fail(i, e);
+        }
+    }
+
+    /// Close client `c`'s sockets and open new ones in their place.
+    fn reconnect(&mut self, c: usize) {
+        self.counters.sessions.fetch_add(1, Ordering::SeqCst);
+        for &i in &self.clients[c].conns {
+            self.io.close(i, &self.conns[i].stream);
+            self.conns[i].closed = true;
+        }
+        let sockets = match self.balancer.connect(self.clients[c].id) {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                eprintln!("Failed to reconnect: {:?}", e);
+                self.counters.record_error();
+                self.recorder.record_error();
+                return self.finish(c);
+            }
+        };
+        for (k, (backend, stream)) in sockets.into_iter().enumerate() {
+            let i = self.clients[c].conns[k];
+            if let Err(e) = self.io.open(i, &stream) {
+                eprintln!("Failed to reconnect: {:?}", e);
+                self.counters.record_error();
+                self.recorder.record_error();
+                return self.finish(c);
+            }
+            let conn = &mut self.conns[i];
+            conn.stream = stream;
+            conn.backend = backend;
+            conn.out.clear();
+            conn.inbuf.clear();
+            conn.closed = false;
+        }
+    }
+
+    /// Stop sending on client `c`.
+    fn finish(&mut self, c: usize) {
+        let client = &mut self.clients[c];
+        if !client.done {
+            client.done = true;
+            self.active -= 1;
+        }
+    }
+
+    /// Give up on socket `i`'s client, along with its other sockets and all of its outstanding
+    /// requests.
+    fn fail(&mut self, i: usize, e: impl std::fmt::Debug) {
+        if self.conns[i].closed {
+            return;
+        }
+        eprintln!("Connection error: {:?}", e);
+        self.counters.record_error();
+        self.recorder.record_error();
+        let c = self.conns[i].client;
+        for &i in &self.clients[c].conns {
+            let conn = &mut self.conns[i];
+            if !conn.closed {
+                self.io.close(i, &conn.stream);
+                conn.closed = true;
+            }
+            let outstanding = std::mem::take(&mut conn.outstanding);
+            self.balancer.completed(conn.backend, outstanding as u64);
+            self.outstanding -= outstanding;
+        }
+        self.clients[c].outstanding = 0;
+        self.finish(c);
+    }
+
+    /// Handle every complete response socket `i` has received.
+    fn receive(&mut self, i: usize) {
+        let conn = &mut self.conns[i];
+        if conn.closed {
+            return;
+        }
+        if let Err(e) = self.balancer.options().message_received(&conn.stream) {
+            return self.fail(i, e);
+        }
+        self.monitor.record_backlog(conn.inbuf.len());
+        let mut responses = Vec::new();
+        let mut consumed = 0;
+        loop {
+            match decode_msg::<ServerWorkPacket>(&conn.inbuf[consumed..]) {
+                Ok(Some((packet, len))) => {
+                    consumed += len;
+                    responses.push(packet);
+                }
+                Ok(None) => break,
+                Err(e) => return self.fail(i, e),
+            }
+        }
+        conn.inbuf.drain(..consumed);
+
+        let recv_timestamp = get_current_time_micros();
+        for packet in responses {
+            self.on_response(i, packet, recv_timestamp);
+        }
+    }
+
+    fn on_response(&mut self, i: usize, packet: ServerWorkPacket, recv_timestamp: u64) {
+        let conn = &mut self.conns[i];
+        if conn.outstanding == 0 {
+            return;
+        }
+        conn.outstanding -= 1;
+        self.clients[conn.client].outstanding -= 1;
+        self.outstanding -= 1;
+        self.balancer.completed(conn.backend, 1);
+        self.counters
+            .record_server_iters_per_us(packet.busy_work_iters_per_us());
+        match packet.calculate_latency(recv_timestamp) {
+            Some(mut latency_record) => {
+                latency_record.backend = conn.backend;
+                self.recorder.record_received(latency_record.latency);
+                self.latencies.push(latency_record);
+            }
+            None => {
+                self.counters.record_error();
+                self.recorder.record_error();
+            }
+        }
+        self.ready.push(conn.client);
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::{spawn, uring::UringIo, Backend, Clients, Next, Requests, UringConfig};
+    use crate::{
+        app::Work,
+        balance::{Balancer, Servers},
+        metrics::MeasurementConfig,
+        socket::SocketOptions,
+        tcp_server::{tcp_server, ServerOptions},
+        timeseries::IntervalRecorder,
+    };
+    use minstant::Instant;
+    use std::{
+        net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+        sync::{atomic::Ordering, Arc},
+        thread,
+        time::Duration,
+    };
+
+    /// Start a server on a free loopback port.
+    fn serve() -> SocketAddr {
+        let addr = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|l| l.local_addr())
+            .expect("find a free port");
+        thread::spawn(move || tcp_server(addr, ServerOptions::default()));
+        while TcpStream::connect(addr).is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        addr
+    }
+
+    /// A closed loop user that sends `requests` requests, `per_session` to each connection.
+    struct Sessions {
+        requests: u64,
+        per_session: u64,
+        /// Requests sent over the current connection.
+        in_session: u64,
+    }
+
+    impl Requests for Sessions {
+        fn next_action(&mut self, now: Instant, outstanding: usize) -> Next {
+            if outstanding > 0 {
+                Next::Wait
+            } else if self.requests == 0 {
+                Next::Done
+            } else if self.in_session == self.per_session {
+                self.in_session = 0;
+                Next::Reconnect(now)
+            } else {
+                self.requests -= 1;
+                self.in_session += 1;
+                Next::Send(now, Work::Immediate, Some(32))
+            }
+        }
+    }
+
+    /// Run `conns` open loop schedules and closed loop sessions of `requests` each against one
+    /// server, and return requests sent, responses received and errors.
+    fn run(backend: Backend, requests: u64) -> (u64, usize, u64) {
+        let balancer = Arc::new(Balancer::new(
+            &Servers::single(serve()),
+            SocketOptions::default(),
+        ));
+        let clients = Clients {
+            threads: 2,
+            connections: 8,
+            backend,
+            sockets: SocketOptions::default(),
+        };
+        let start = Instant::now();
+        let conns = (0..clients.connections)
+            .map(|id| {
+                let sockets = balancer.connect(id).expect("connect");
+                let requests: Box<dyn Requests> = if id % 2 == 0 {
+                    // Many requests back to back, so responses arrive in partial frames.
+                    Box::new(Box::new((0..requests).map(move |k| {
+                        let at = start + Duration::from_micros(20 * k);
+                        (at, Work::Immediate, Some(k as usize % 64))
+                    }))
+                        as Box<dyn Iterator<Item = (Instant, Work, Option<usize>)> + Send>)
+                } else {
+                    Box::new(Sessions {
+                        requests,
+                        per_session: 10,
+                        in_session: 0,
+                    })
+                };
+                (sockets, requests)
+            })
+            .collect();
+        let recorder = Arc::new(IntervalRecorder::new(false));
+        let (mut sent, mut received, mut errors) = (0, 0, 0);
+        for (handle, counters) in spawn(
+            balancer,
+            clients,
+            conns,
+            start,
+            recorder,
+            &MeasurementConfig::default(),
+        ) {
+            received += handle.join().expect("engine thread").latencies.len();
+            sent += counters.sent.load(Ordering::SeqCst);
+            errors += counters.errors.load(Ordering::SeqCst);
+        }
+        (sent, received, errors)
+    }
+
+    #[test]
+    fn epoll_completes_every_request() {
+        assert_eq!(run(Backend::Epoll, 500), (8 * 500, 8 * 500, 0));
+    }
+
+    #[test]
+    fn uring_completes_every_request() {
+        assert_eq!(
+            run(Backend::Uring(UringConfig::default()), 500),
+            (8 * 500, 8 * 500, 0)
+        );
+    }
+
+    #[test]
+    fn uring_sqpoll_registered_buffers_complete_every_request() {
+        let config = UringConfig {
+            sqpoll: true,
+            registered_buffers: true,
+        };
+        // SQPOLL and registered buffers need privileges or resources a kernel may refuse.
+        if let Err(e) = UringIo::new(8, config, SocketOptions::default()) {
+            eprintln!("Skipping: io_uring refused {:?}: {:?}", config, e);
+            return;
+        }
+        assert_eq!(run(Backend::Uring(config), 500), (8 * 500, 8 * 500, 0));
+    }
+}
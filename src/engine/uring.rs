@@ -0,0 +1,270 @@
+//! [`Io`] through io_uring, so that sends and receives need no syscall each.
+
+use super::{Conn, Io, IoEvent};
+use crate::socket::SocketOptions;
+use io_uring::{opcode, squeue, types, IoUring};
+use std::{
+    io,
+    net::{Shutdown, TcpStream},
+    os::fd::{AsRawFd, RawFd},
+    time::Duration,
+};
+
+/// Bytes of each connection's receive buffer, and of its send buffer.
+const BUF_SIZE: usize = 1024;
+
+/// How long the kernel's submission queue polling thread spins before it sleeps.
+const SQPOLL_IDLE_MS: u32 = 100;
+
+const OP_RECV: u64 = 0;
+const OP_SEND: u64 = 1;
+
+/// Options for the [`Backend::Uring`](super::Backend::Uring) engine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UringConfig {
+    /// Have a kernel thread poll the submission queue, so submitting usually needs no syscall.
+    pub sqpoll: bool,
+    /// Register every connection's buffers with the kernel up front, so it needn't map them
+    /// for each operation.
+    pub registered_buffers: bool,
+}
+
+/// One connection's state in the ring.
+struct UringConn {
+    fd: RawFd,
+    /// Bumped whenever the connection's socket is closed, so that completions for the old socket
+    /// can be told apart and ignored.
+    generation: u32,
+    /// Bytes at the front of the send buffer that the kernel hasn't sent yet.
+    sending: usize,
+}
+
+pub(super) struct UringIo {
+    // Declared before `slab` so that it's dropped first.
+    ring: IoUring,
+    config: UringConfig,
+    sockets: SocketOptions,
+    /// Each connection's receive buffer followed by its send buffer. Never resized, since the
+    /// kernel holds pointers into it.
+    slab: Vec<u8>,
+    conns: Vec<UringConn>,
+    /// Submitted operations whose completions haven't been seen yet.
+    in_flight: usize,
+}
+
+impl UringIo {
+    pub(super) fn new(
+        num_conns: usize,
+        config: UringConfig,
+        sockets: SocketOptions,
+    ) -> io::Result<Self> {
+        // Each connection has at most one receive and one send in flight.
+        let entries = (2 * num_conns).next_power_of_two().clamp(8, 32768) as u32;
+        let mut builder = IoUring::builder();
+        if config.sqpoll {
+            builder.setup_sqpoll(SQPOLL_IDLE_MS);
+        }
+        let ring = builder.setup_cqsize(2 * entries).build(entries)?;
+        let mut slab = vec![0; 2 * BUF_SIZE * num_conns.max(1)];
+        if config.registered_buffers {
+            let iovec = libc::iovec {
+                iov_base: slab.as_mut_ptr().cast(),
+                iov_len: slab.len(),
+            };
+            // Safety: `slab` outlives the ring and is never reallocated.
+            unsafe { ring.submitter().register_buffers(&[iovec])? };
+        }
+        Ok(Self {
+            ring,
+            config,
+            sockets,
+            slab,
+            conns: (0..num_conns)
+                .map(|_| UringConn {
+                    fd: -1,
+                    generation: 0,
+                    sending: 0,
+                })
+                .collect(),
+            in_flight: 0,
+        })
+    }
+
+    fn user_data(&self, i: usize, op: u64) -> u64 {
+        (i as u64) << 32 | (self.conns[i].generation as u64) << 1 | op
+    }
+
+    fn recv_buf(&mut self, i: usize) -> *mut u8 {
+        self.slab[2 * BUF_SIZE * i..].as_mut_ptr()
+    }
+
+    fn send_buf(&mut self, i: usize) -> *mut u8 {
+        self.slab[2 * BUF_SIZE * i + BUF_SIZE..].as_mut_ptr()
+    }
+
+    fn push(&mut self, entry: squeue::Entry) -> io::Result<()> {
+        // Safety: every buffer an entry points into is part of `slab`, which outlives the ring.
+        while unsafe { self.ring.submission().push(&entry) }.is_err() {
+            self.ring.submit()?;
+        }
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    fn submit_recv(&mut self, i: usize) -> io::Result<()> {
+        let fd = types::Fd(self.conns[i].fd);
+        let buf = self.recv_buf(i);
+        let entry = if self.config.registered_buffers {
+            opcode::ReadFixed::new(fd, buf, BUF_SIZE as u32, 0).build()
+        } else {
+            opcode::Recv::new(fd, buf, BUF_SIZE as u32).build()
+        };
+        self.push(entry.user_data(self.user_data(i, OP_RECV)))
+    }
+
+    fn submit_send(&mut self, i: usize) -> io::Result<()> {
+        let fd = types::Fd(self.conns[i].fd);
+        let buf = self.send_buf(i);
+        let len = self.conns[i].sending as u32;
+        let entry = if self.config.registered_buffers {
+            opcode::WriteFixed::new(fd, buf, len, 0).build()
+        } else {
+            opcode::Send::new(fd, buf, len).build()
+        };
+        self.push(entry.user_data(self.user_data(i, OP_SEND)))
+    }
+
+    fn complete(
+        &mut self,
+        i: usize,
+        op: u64,
+        result: i32,
+        conn: &mut Conn,
+        events: &mut Vec<IoEvent>,
+    ) -> io::Result<()> {
+        if result < 0 {
+            let e = io::Error::from_raw_os_error(-result);
+            return match e.kind() {
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock if op == OP_RECV => {
+                    self.submit_recv(i)
+                }
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => self.submit_send(i),
+                _ => Err(e),
+            };
+        }
+        let n = result as usize;
+        if op == OP_RECV {
+            if n == 0 {
+                events.push(IoEvent::Closed(i));
+                return Ok(());
+            }
+            let start = 2 * BUF_SIZE * i;
+            conn.inbuf.extend_from_slice(&self.slab[start..start + n]);
+            events.push(IoEvent::Received(i));
+            return self.submit_recv(i);
+        }
+
+        let start = 2 * BUF_SIZE * i + BUF_SIZE;
+        let sending = self.conns[i].sending;
+        self.slab.copy_within(start + n..start + sending, start);
+        self.conns[i].sending -= n;
+        if self.conns[i].sending > 0 {
+            self.submit_send(i)
+        } else if conn.out.is_empty() {
+            self.sockets.message_sent(&self.conns[i].fd)
+        } else {
+            self.flush(i, conn)
+        }
+    }
+}
+
+impl Io for UringIo {
+    fn open(&mut self, i: usize, stream: &TcpStream) -> io::Result<()> {
+        // io_uring fails operations on non-blocking sockets that aren't ready instead of waiting.
+        stream.set_nonblocking(false)?;
+        let conn = &mut self.conns[i];
+        conn.fd = stream.as_raw_fd();
+        conn.sending = 0;
+        self.submit_recv(i)
+    }
+
+    fn close(&mut self, i: usize, stream: &TcpStream) {
+        // Hand the kernel any operations still queued for the socket first. Once it's closed its
+        // descriptor can be reused by a new socket, whose data they'd then take.
+        while self.ring.submit().is_ok() && !self.ring.submission().is_empty() {
+            std::hint::spin_loop();
+        }
+        // Shutting the socket down completes its outstanding receive, which would otherwise keep
+        // it open.
+        let _ = stream.shutdown(Shutdown::Both);
+        let conn = &mut self.conns[i];
+        conn.generation = conn.generation.wrapping_add(1) & 0x7fff_ffff;
+        conn.sending = 0;
+    }
+
+    fn flush(&mut self, i: usize, conn: &mut Conn) -> io::Result<()> {
+        if self.conns[i].sending > 0 || conn.out.is_empty() {
+            return Ok(());
+        }
+        let n = conn.out.len().min(BUF_SIZE);
+        let start = 2 * BUF_SIZE * i + BUF_SIZE;
+        self.slab[start..start + n].copy_from_slice(&conn.out[..n]);
+        conn.out.drain(..n);
+        self.conns[i].sending = n;
+        self.submit_send(i)
+    }
+
+    fn poll(
+        &mut self,
+        conns: &mut [Conn],
+        timeout: Duration,
+        events: &mut Vec<IoEvent>,
+    ) -> io::Result<()> {
+        if timeout.is_zero() {
+            self.ring.submit()?;
+        } else {
+            let ts = types::Timespec::from(timeout);
+            let args = types::SubmitArgs::new().timespec(&ts);
+            match self.ring.submitter().submit_with_args(1, &args) {
+                Ok(_) => {}
+                Err(e) if matches!(e.raw_os_error(), Some(libc::ETIME | libc::EINTR)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let completions: Vec<_> = self
+            .ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result()))
+            .collect();
+        for (user_data, result) in completions {
+            self.in_flight -= 1;
+            let i = (user_data >> 32) as usize;
+            let generation = (user_data as u32) >> 1;
+            if generation != self.conns[i].generation {
+                continue;
+            }
+            if let Err(e) = self.complete(i, user_data & 1, result, &mut conns[i], events) {
+                events.push(IoEvent::Failed(i, e));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UringIo {
+    fn drop(&mut self) {
+        // The kernel may still write into `slab` until every operation completes, so shut every
+        // socket down and wait them out.
+        for conn in &self.conns {
+            // Safety: the sockets are owned by the driver's connections, which outlive this.
+            unsafe { libc::shutdown(conn.fd, libc::SHUT_RDWR) };
+        }
+        while self.in_flight > 0 {
+            if self.ring.submit_and_wait(1).is_err() {
+                break;
+            }
+            self.in_flight -= self.ring.completion().count();
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Downstream calls made by [`Work::FanOut`](crate::app::Work::FanOut) requests.
+//!
+//! Each server connection thread keeps its own connection to every backend, and one reader
+//! thread per backend connection forwards responses to the connection thread as they arrive, so
+//! a request can return as soon as a quorum of its sub-requests have.
+
+use crate::{
+    app::Work,
+    protocol::{work_request::ClientWorkPacketConn, work_response::ServerWorkPacketConn},
+    serialize::{ClientWorkPacket, ServerWorkPacket},
+    socket::{Endpoint, SocketOptions},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long to wait for a quorum before giving up on a fan-out request.
+const QUORUM_TIMEOUT: Duration = Duration::from_secs(5);
+
+static BACKENDS: OnceLock<Vec<Endpoint>> = OnceLock::new();
+
+/// Set the backend servers that fan-out requests call, connecting with `options`. Only the first
+/// call has any effect.
+pub fn set_backends(backends: Vec<SocketAddr>, options: SocketOptions) {
+    let backends = backends
+        .into_iter()
+        .map(|addr| Endpoint { addr, options })
+        .collect();
+    let _ = BACKENDS.set(backends);
+}
+
+fn backends() -> &'static [Endpoint] {
+    BACKENDS.get().map_or(&[], Vec::as_slice)
+}
+
+/// Timing of one fan-out request's sub-requests, as seen by the server that issued them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FanOutTiming {
+    /// Time from sending the sub-requests to the first response, in us.
+    pub first_us: u64,
+    /// Time from sending the sub-requests to the quorum-th response, in us.
+    pub quorum_us: u64,
+    /// Longest backend processing time among the quorum, in us.
+    pub backend_us: u64,
+}
+
+/// One connection thread's connections to every backend.
+struct Pool {
+    senders: Vec<ClientWorkPacketConn>,
+    streams: Vec<TcpStream>,
+    options: SocketOptions,
+    responses: Receiver<(ServerWorkPacket, Instant)>,
+    next_id: u64,
+    next_backend: usize,
+}
+
+impl Pool {
+    fn connect(backends: &[Endpoint]) -> Result<Self, anyhow::Error> {
+        let (tx, responses) = mpsc::channel();
+        let mut senders = Vec::new();
+        let mut streams = Vec::new();
+        for backend in backends {
+            let stream = backend.connect()?;
+            senders.push(ClientWorkPacketConn::new(&stream));
+            spawn_reader(stream.try_clone()?, backend.options, tx.clone());
+            streams.push(stream);
+        }
+        Ok(Self {
+            senders,
+            streams,
+            options: backends[0].options,
+            responses,
+            next_id: 0,
+            next_backend: 0,
+        })
+    }
+
+    fn call(&mut self, fanout: u64, quorum: u64, leaf: u64) -> Result<FanOutTiming, anyhow::Error> {
+        // Responses to earlier requests that arrived after their quorum have ids below `base`.
+        let base = self.next_id;
+        self.next_id += fanout;
+
+        let start = Instant::now();
+        for id in base..base + fanout {
+            let backend = self.next_backend;
+            self.next_backend = (backend + 1) % self.senders.len();
+            self.senders[backend].send_work_msg(ClientWorkPacket::new(id, Work::Const(leaf)))?;
+            self.options.message_sent(&self.streams[backend])?;
+        }
+
+        let mut received = 0;
+        let mut first_us = 0;
+        let mut backend_us = 0;
+        while received < quorum {
+            let (packet, recv_time) = self.responses.recv_timeout(QUORUM_TIMEOUT)?;
+            if packet.client_id() < base {
+                continue;
+            }
+            let Some(record) = packet.calculate_latency(crate::get_current_time_micros()) else {
+                anyhow::bail!("backend failed sub-request {}", packet.client_id());
+            };
+            if received == 0 {
+                first_us = (recv_time - start).as_micros() as u64;
+            }
+            backend_us = backend_us.max(record.server_processing_time);
+            received += 1;
+        }
+        Ok(FanOutTiming {
+            first_us,
+            quorum_us: start.elapsed().as_micros() as u64,
+            backend_us,
+        })
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // Wake the reader threads so they exit.
+        for stream in &self.streams {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Forward every response on `stream` to `tx` until the connection or the channel closes.
+fn spawn_reader(
+    stream: TcpStream,
+    options: SocketOptions,
+    tx: Sender<(ServerWorkPacket, Instant)>,
+) {
+    thread::spawn(move || {
+        let mut conn = ServerWorkPacketConn::new(&stream);
+        while let Ok(packet) = conn.recv_work_msg() {
+            let recv_time = Instant::now();
+            if options.message_received(&stream).is_err() || tx.send((packet, recv_time)).is_err()
+            {
+                break;
+            }
+        }
+    });
+}
+
+/// Send `fanout` sub-requests, each doing `leaf` us of [`Work::Const`], round-robin over the
+/// configured backends, and wait for `quorum` of them to return.
+///
+/// Connections are opened on a thread's first fan-out request and reused until one of them fails.
+pub fn call(fanout: u64, quorum: u64, leaf: u64) -> Result<FanOutTiming, anyhow::Error> {
+    thread_local! {
+        static POOL: RefCell<Option<Pool>> = const { RefCell::new(None) };
+    }
+
+    if backends().is_empty() {
+        anyhow::bail!("fan-out request but no backends are configured");
+    }
+    POOL.with_borrow_mut(|pool| {
+        if pool.is_none() {
+            *pool = Some(Pool::connect(backends())?);
+        }
+        let result = pool.as_mut().unwrap().call(fanout, quorum, leaf);
+        if result.is_err() {
+            // Reconnect on the next request rather than reuse connections in an unknown state.
+            *pool = None;
+        }
+        result
+    })
+}
@@ -0,0 +1,347 @@
+//! Self-monitoring of the load generator, to tell when a run measured the client rather than the
+//! server.
+//!
+//! Each client thread tracks its own CPU time, how far behind schedule it sends requests, and how
+//! many response bytes have piled up by the time it reads them. A client that can't keep to its
+//! schedule or keep up with responses reports its own queueing as server latency, and offering it
+//! more load won't load the server any harder.
+
+use crate::metrics::{percentile, Percentiles};
+use nix::sys::{
+    resource::{getrusage, UsageWho},
+    time::{TimeVal, TimeValLike},
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    os::fd::AsRawFd,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// A send this far behind its scheduled time counts as late.
+pub const LATE_SEND: Duration = Duration::from_millis(1);
+
+/// The client is considered the bottleneck if more than this fraction of its sends are late,
+const LATE_FRACTION: f64 = 0.1;
+
+/// or if the 99th percentile receive backlog reaches this many bytes,
+const BACKLOG_BYTES: u64 = 16 * 1024;
+
+/// or if a closed loop run kept this fraction of the machine's CPUs busy. Open loop senders spin
+/// while waiting to send, so their CPU use says nothing on its own.
+const CPU_SATURATED: f64 = 0.9;
+
+fn cpu_time(who: UsageWho) -> Duration {
+    fn duration(tv: TimeVal) -> Duration {
+        Duration::from_micros(tv.num_microseconds().max(0) as u64)
+    }
+    match getrusage(who) {
+        Ok(usage) => duration(usage.user_time()) + duration(usage.system_time()),
+        Err(_) => Duration::ZERO,
+    }
+}
+
+/// Bytes waiting to be read from `stream`'s receive queue.
+pub fn queued_bytes(stream: &impl AsRawFd) -> usize {
+    let mut n: libc::c_int = 0;
+    // Safety: FIONREAD writes one int through the pointer.
+    match unsafe { libc::ioctl(stream.as_raw_fd(), libc::FIONREAD, &mut n) } {
+        0 => n.max(0) as usize,
+        _ => 0,
+    }
+}
+
+/// What a client thread does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Sends an open loop connection's requests.
+    Send,
+    /// Receives an open loop connection's responses.
+    Recv,
+    /// Runs one closed loop user.
+    User,
+    /// Drives many connections on the event-driven engine.
+    Engine,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Send => write!(f, "send"),
+            Self::Recv => write!(f, "recv"),
+            Self::User => write!(f, "user"),
+            Self::Engine => write!(f, "engine"),
+        }
+    }
+}
+
+/// Measurements a client thread takes of itself. Must be started and finished on the thread it
+/// measures.
+pub struct ThreadMonitor {
+    role: Role,
+    start: Instant,
+    cpu_start: Duration,
+    slips_us: Vec<u64>,
+    backlogs: Vec<u64>,
+}
+
+impl ThreadMonitor {
+    pub fn start(role: Role) -> Self {
+        Self {
+            role,
+            start: Instant::now(),
+            cpu_start: cpu_time(UsageWho::RUSAGE_THREAD),
+            slips_us: Vec::new(),
+            backlogs: Vec::new(),
+        }
+    }
+
+    /// Record a request sent `slip` after its scheduled time.
+    pub fn record_slip(&mut self, slip: Duration) {
+        self.slips_us.push(slip.as_micros() as u64);
+    }
+
+    /// Record that `bytes` of responses were waiting when the thread went to read them.
+    pub fn record_backlog(&mut self, bytes: usize) {
+        self.backlogs.push(bytes as u64);
+    }
+
+    pub fn finish(mut self) -> ThreadUsage {
+        self.slips_us.sort_unstable();
+        self.backlogs.sort_unstable();
+        ThreadUsage {
+            role: self.role,
+            cpu: cpu_time(UsageWho::RUSAGE_THREAD).saturating_sub(self.cpu_start),
+            wall: self.start.elapsed(),
+            slips_us: self.slips_us,
+            backlogs: self.backlogs,
+        }
+    }
+}
+
+/// One client thread's measurements of itself over a run.
+#[derive(Debug, Clone)]
+pub struct ThreadUsage {
+    pub role: Role,
+    /// User and system CPU time the thread used.
+    pub cpu: Duration,
+    pub wall: Duration,
+    /// How late each scheduled send went out, in us, sorted.
+    pub slips_us: Vec<u64>,
+    /// Response bytes waiting at each read, sorted.
+    pub backlogs: Vec<u64>,
+}
+
+impl ThreadUsage {
+    /// Fraction of the thread's lifetime it spent on a CPU.
+    pub fn cpu_util(&self) -> f64 {
+        match self.wall.as_secs_f64() {
+            0.0 => 0.0,
+            wall => self.cpu.as_secs_f64() / wall,
+        }
+    }
+
+    /// Sends more than [`LATE_SEND`] behind schedule.
+    pub fn late_sends(&self) -> usize {
+        let late = LATE_SEND.as_micros() as u64;
+        self.slips_us.len() - self.slips_us.partition_point(|&s| s <= late)
+    }
+}
+
+/// CPU time of the whole client process over a run.
+pub struct ProcessMonitor {
+    start: Instant,
+    cpu_start: Duration,
+}
+
+impl ProcessMonitor {
+    pub fn start() -> Self {
+        Self {
+            start: Instant::now(),
+            cpu_start: cpu_time(UsageWho::RUSAGE_SELF),
+        }
+    }
+
+    /// Fraction of the machine's CPUs the process kept busy since it started.
+    pub fn finish(self) -> f64 {
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let cpu = cpu_time(UsageWho::RUSAGE_SELF).saturating_sub(self.cpu_start);
+        match self.start.elapsed().as_secs_f64() * cpus as f64 {
+            0.0 => 0.0,
+            capacity => cpu.as_secs_f64() / capacity,
+        }
+    }
+}
+
+/// How hard the client worked during a run, and whether it kept up.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GeneratorSummary {
+    /// Fraction of the machine's CPUs the client process kept busy.
+    pub process_cpu: f64,
+    /// CPU utilization of the busiest client thread.
+    pub max_thread_cpu: f64,
+    /// Percentiles of how late scheduled sends went out, in us, over all threads.
+    pub slip: Option<Percentiles>,
+    pub max_slip_us: u64,
+    /// Fraction of scheduled sends more than [`LATE_SEND`] behind schedule.
+    pub late_fraction: f64,
+    /// 99th percentile and maximum response bytes waiting when a thread went to read them.
+    pub backlog_p99: u64,
+    pub backlog_max: u64,
+    /// Whether the client rather than the server likely limited the run.
+    pub bottleneck: bool,
+}
+
+impl GeneratorSummary {
+    /// Summarize `threads`, given the process's CPU use. Sends of `open_loop` runs follow a
+    /// schedule regardless of responses.
+    pub fn new(threads: &[ThreadUsage], process_cpu: f64, open_loop: bool) -> Self {
+        let mut slips: Vec<u64> = threads.iter().flat_map(|t| &t.slips_us).copied().collect();
+        let mut backlogs: Vec<u64> = threads.iter().flat_map(|t| &t.backlogs).copied().collect();
+        let late = threads.iter().map(ThreadUsage::late_sends).sum::<usize>();
+        let late_fraction = match slips.len() {
+            0 => 0.0,
+            n => late as f64 / n as f64,
+        };
+        backlogs.sort_unstable();
+        let (backlog_p99, backlog_max) = match backlogs.last() {
+            Some(&max) => (percentile(&backlogs, 0.99), max),
+            None => (0, 0),
+        };
+        Self {
+            process_cpu,
+            max_thread_cpu: threads.iter().map(ThreadUsage::cpu_util).fold(0.0, f64::max),
+            slip: Percentiles::from_unsorted(&mut slips),
+            max_slip_us: slips.last().copied().unwrap_or(0),
+            late_fraction,
+            backlog_p99,
+            backlog_max,
+            bottleneck: late_fraction > LATE_FRACTION
+                || backlog_p99 >= BACKLOG_BYTES
+                || (!open_loop && process_cpu >= CPU_SATURATED),
+        }
+    }
+}
+
+/// Summarize the client's measurements of itself, print them with a warning if the client was
+/// the bottleneck, and write each thread's to `outdir/client.csv`.
+pub fn report(
+    outdir: &Path,
+    threads: &[ThreadUsage],
+    process_cpu: f64,
+    open_loop: bool,
+) -> GeneratorSummary {
+    let summary = GeneratorSummary::new(threads, process_cpu, open_loop);
+    println!("\nClient Resources:");
+    println!(
+        "CPU: {:.1}% of all cores, busiest thread {:.1}%",
+        100.0 * summary.process_cpu,
+        100.0 * summary.max_thread_cpu
+    );
+    if let Some(slip) = summary.slip {
+        println!(
+            "Send slip: p50 {:.0} us, p99 {:.0} us, max {} us, {:.2}% more than {} us late",
+            slip.p50,
+            slip.p99,
+            summary.max_slip_us,
+            100.0 * summary.late_fraction,
+            LATE_SEND.as_micros()
+        );
+    }
+    if threads.iter().any(|t| !t.backlogs.is_empty()) {
+        println!(
+            "Receive backlog: p99 {} bytes, max {} bytes",
+            summary.backlog_p99, summary.backlog_max
+        );
+    }
+    if summary.bottleneck {
+        println!(
+            "WARNING: the client could not keep up with the load it was asked to generate, so \
+             these results measure the client as much as the server. Use more client threads or \
+             the epoll/uring backend, or move the client to another machine."
+        );
+    }
+
+    if let Err(e) = write_threads(outdir, threads) {
+        eprintln!("Failed to write client resource usage: {:?}", e);
+    }
+    summary
+}
+
+fn write_threads(outdir: &Path, threads: &[ThreadUsage]) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(outdir)?;
+    let mut out = BufWriter::new(File::create(outdir.join("client.csv"))?);
+    writeln!(
+        out,
+        "thread,role,cpu_us,wall_us,cpu_util,sends,slip_p50_us,slip_p99_us,slip_max_us,late_sends,backlog_p99_bytes,backlog_max_bytes"
+    )?;
+    for (i, t) in threads.iter().enumerate() {
+        let slip = match t.slips_us.last() {
+            Some(max) => format!(
+                "{},{},{}",
+                percentile(&t.slips_us, 0.5),
+                percentile(&t.slips_us, 0.99),
+                max
+            ),
+            None => ",,".into(),
+        };
+        let backlog = match t.backlogs.last() {
+            Some(max) => format!("{},{}", percentile(&t.backlogs, 0.99), max),
+            None => ",".into(),
+        };
+        writeln!(
+            out,
+            "{},{},{},{},{:.3},{},{},{},{}",
+            i,
+            t.role,
+            t.cpu.as_micros(),
+            t.wall.as_micros(),
+            t.cpu_util(),
+            t.slips_us.len(),
+            slip,
+            t.late_sends(),
+            backlog
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::{GeneratorSummary, Role, ThreadUsage};
+    use std::time::Duration;
+
+    fn usage(slips_us: Vec<u64>, backlogs: Vec<u64>) -> ThreadUsage {
+        ThreadUsage {
+            role: Role::Engine,
+            cpu: Duration::from_millis(500),
+            wall: Duration::from_secs(1),
+            slips_us,
+            backlogs,
+        }
+    }
+
+    #[test]
+    fn bottleneck_detection() {
+        let on_time = usage(vec![0; 100], vec![0; 100]);
+        let summary = GeneratorSummary::new(std::slice::from_ref(&on_time), 0.5, true);
+        assert!(!summary.bottleneck);
+        assert_eq!(summary.max_thread_cpu, 0.5);
+        assert_eq!(summary.late_fraction, 0.0);
+
+        let late = usage((0..100).map(|i| i * 100).collect(), Vec::new());
+        let summary = GeneratorSummary::new(&[on_time.clone(), late], 0.5, true);
+        assert!(summary.bottleneck);
+        assert_eq!(summary.late_fraction, 89.0 / 200.0);
+        assert_eq!(summary.max_slip_us, 9900);
+
+        let backlogged = usage(Vec::new(), vec![64 * 1024; 10]);
+        assert!(GeneratorSummary::new(&[backlogged], 0.5, true).bottleneck);
+
+        // Saturated CPUs only count against closed loop runs.
+        assert!(!GeneratorSummary::new(std::slice::from_ref(&on_time), 0.95, true).bottleneck);
+        assert!(GeneratorSummary::new(&[on_time], 0.95, false).bottleneck);
+    }
+}
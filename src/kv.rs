@@ -0,0 +1,237 @@
+//! In-memory key-value store served by [`Work::Get`] and [`Work::Set`], and the client-side
+//! workload that generates those requests.
+
+use crate::app::{Work, WorkParseErr};
+use rand::Rng;
+use rand_distr::{Distribution, Zipf};
+use std::{
+    collections::HashMap,
+    num::NonZeroU64,
+    sync::{OnceLock, RwLock},
+};
+
+/// Number of independently locked shards in the [`KvStore`].
+const SHARDS: usize = 64;
+
+/// A concurrent hash map from keys to values, shared by every server connection thread.
+///
+/// Keys are spread over [`SHARDS`] `RwLock`ed maps, so connections contend only when they touch
+/// the same shard.
+pub struct KvStore {
+    shards: Vec<RwLock<HashMap<u64, Vec<u8>>>>,
+}
+
+impl Default for KvStore {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+        }
+    }
+}
+
+impl KvStore {
+    fn shard(&self, key: u64) -> &RwLock<HashMap<u64, Vec<u8>>> {
+        &self.shards[key as usize % SHARDS]
+    }
+
+    /// A copy of the value stored at `key`, if any.
+    pub fn get(&self, key: u64) -> Option<Vec<u8>> {
+        self.shard(key).read().unwrap().get(&key).cloned()
+    }
+
+    /// Store a `value_len`-byte value at `key`, replacing any existing value. Values are cut to
+    /// [`MAX_VALUE_LEN`] bytes, since their length comes from the client.
+    pub fn set(&self, key: u64, value_len: u64) {
+        let value = vec![key as u8; value_len.min(MAX_VALUE_LEN) as usize];
+        self.shard(key).write().unwrap().insert(key, value);
+    }
+}
+
+/// Largest value a [`Work::Set`] request stores, in bytes.
+pub const MAX_VALUE_LEN: u64 = 1 << 20;
+
+/// Parse a value length of at most [`MAX_VALUE_LEN`] bytes.
+pub(crate) fn parse_value_len(s: &str) -> Result<u64, WorkParseErr> {
+    match s.parse()? {
+        len if len > MAX_VALUE_LEN => Err(WorkParseErr::InvalidParam(
+            "value length must be at most 1048576 bytes",
+        )),
+        len => Ok(len),
+    }
+}
+
+/// The server's store.
+pub fn store() -> &'static KvStore {
+    static STORE: OnceLock<KvStore> = OnceLock::new();
+    STORE.get_or_init(KvStore::default)
+}
+
+/// How a [`KvWorkload`] picks keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyDist {
+    Uniform,
+    /// Zipfian with exponent `s`; key 0 is the most popular.
+    Zipf(f64),
+}
+
+/// A memcached-like mix of [`Work::Get`] and [`Work::Set`] requests over a fixed key space.
+///
+/// Implements [`FromStr`](std::str::FromStr). String format is
+/// `kv:[keys],[read_ratio],[value_len]` for uniformly chosen keys, or
+/// `kv:[keys],[read_ratio],[value_len],[zipf_s]` for Zipfian keys. `keys` must be nonzero,
+/// `read_ratio` is the f64 fraction of requests that are gets, and `zipf_s` is a positive f64.
+#[derive(Debug, Clone, Copy)]
+pub struct KvWorkload {
+    pub keys: NonZeroU64,
+    pub read_ratio: f64,
+    pub value_len: u64,
+    pub dist: KeyDist,
+    /// The sampler for a Zipfian `dist`, built once since building it is costly.
+    zipf: Option<Zipf<f64>>,
+}
+
+impl PartialEq for KvWorkload {
+    fn eq(&self, other: &Self) -> bool {
+        (self.keys, self.read_ratio, self.value_len, self.dist)
+            == (other.keys, other.read_ratio, other.value_len, other.dist)
+    }
+}
+
+impl KvWorkload {
+    /// Pick the key and operation of the next request.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Work {
+        let key = match self.zipf {
+            None => rng.gen_range(0..self.keys.get()),
+            Some(zipf) => {
+                // Zipf samples ranks in [1, keys].
+                let rank: f64 = zipf.sample(rng);
+                rank as u64 - 1
+            }
+        };
+        if rng.gen_bool(self.read_ratio) {
+            Work::Get(key)
+        } else {
+            Work::Set {
+                key,
+                value_len: self.value_len,
+            }
+        }
+    }
+
+    /// Whether `work` is a request this workload could generate.
+    pub fn generates(&self, work: &Work) -> bool {
+        match *work {
+            Work::Get(key) => key < self.keys.get(),
+            Work::Set { key, value_len } => key < self.keys.get() && value_len == self.value_len,
+            _ => false,
+        }
+    }
+}
+
+impl std::str::FromStr for KvWorkload {
+    type Err = WorkParseErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(params) = s.strip_prefix("kv:") else {
+            return Err(WorkParseErr::UnknownFmt(s.to_owned()));
+        };
+        let params: Vec<_> = params.split(',').collect();
+        let (keys, read_ratio, value_len, dist) = match &params[..] {
+            [keys, read_ratio, value_len] => (keys, read_ratio, value_len, KeyDist::Uniform),
+            [keys, read_ratio, value_len, zipf_s] => {
+                let zipf_s: f64 = zipf_s.parse()?;
+                if !(zipf_s > 0.0 && zipf_s.is_finite()) {
+                    return Err(WorkParseErr::InvalidParam("zipf exponent must be positive"));
+                }
+                (keys, read_ratio, value_len, KeyDist::Zipf(zipf_s))
+            }
+            _ => return Err(WorkParseErr::UnknownFmt(s.to_owned())),
+        };
+        let read_ratio: f64 = read_ratio.parse()?;
+        if !(0.0..=1.0).contains(&read_ratio) {
+            return Err(WorkParseErr::InvalidParam("read ratio must be in [0, 1]"));
+        }
+        let keys = NonZeroU64::new(keys.parse()?).ok_or(WorkParseErr::ZeroSize("key space"))?;
+        let zipf = match dist {
+            KeyDist::Uniform => None,
+            KeyDist::Zipf(s) => Some(Zipf::new(keys.get(), s).expect("zipf exponent is positive")),
+        };
+        Ok(Self {
+            keys,
+            read_ratio,
+            value_len: parse_value_len(value_len)?,
+            dist,
+            zipf,
+        })
+    }
+}
+
+impl std::fmt::Display for KvWorkload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "kv:{},{},{}", self.keys, self.read_ratio, self.value_len)?;
+        if let KeyDist::Zipf(s) = self.dist {
+            write!(f, ",{}", s)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::{KeyDist, KvStore, KvWorkload};
+    use crate::app::{Work, WorkParseErr};
+
+    #[test]
+    fn parse_kv_workload() {
+        let kv: KvWorkload = "kv:1000,0.9,100".parse().expect("parse uniform");
+        assert_eq!(kv.keys.get(), 1000);
+        assert_eq!(kv.read_ratio, 0.9);
+        assert_eq!(kv.value_len, 100);
+        assert_eq!(kv.dist, KeyDist::Uniform);
+        assert_eq!(kv.to_string(), "kv:1000,0.9,100");
+
+        let kv: KvWorkload = "kv:1000,0.5,8,0.99".parse().expect("parse zipf");
+        assert_eq!(kv.dist, KeyDist::Zipf(0.99));
+        assert_eq!(kv.to_string(), "kv:1000,0.5,8,0.99");
+
+        assert!(matches!(
+            "kv:0,0.9,100".parse::<KvWorkload>(),
+            Err(WorkParseErr::ZeroSize(_))
+        ));
+        assert!(matches!(
+            "kv:10,1.5,100".parse::<KvWorkload>(),
+            Err(WorkParseErr::InvalidParam(_))
+        ));
+        assert!(matches!(
+            "kv:10,0.5,100,0".parse::<KvWorkload>(),
+            Err(WorkParseErr::InvalidParam(_))
+        ));
+        assert!(matches!(
+            "kv:10,0.5".parse::<KvWorkload>(),
+            Err(WorkParseErr::UnknownFmt(_))
+        ));
+    }
+
+    #[test]
+    fn kv_workload_samples_in_range() {
+        let mut rng = rand::thread_rng();
+        for spec in ["kv:50,0.5,16", "kv:50,0.5,16,1.2"] {
+            let kv: KvWorkload = spec.parse().unwrap();
+            for _ in 0..1000 {
+                let work = kv.sample(&mut rng);
+                assert!(kv.generates(&work), "{} generated {}", spec, work);
+            }
+        }
+        let reads: KvWorkload = "kv:50,1,16".parse().unwrap();
+        assert!(matches!(reads.sample(&mut rng), Work::Get(_)));
+    }
+
+    #[test]
+    fn store_get_set() {
+        let store = KvStore::default();
+        assert_eq!(store.get(7), None);
+        store.set(7, 3);
+        assert_eq!(store.get(7), Some(vec![7; 3]));
+        store.set(7, 1);
+        assert_eq!(store.get(7), Some(vec![7]));
+    }
+}
@@ -1,12 +1,27 @@
 //! CS1675 network APIs project.
 
+pub mod affinity;
 pub mod app;
+pub mod balance;
+pub mod capacity;
 pub mod chunked_tcp_stream;
 pub mod closed_loop_client;
+pub mod engine;
+pub mod fanout;
+pub mod generator;
+pub mod kv;
+pub mod manifest;
+pub mod metrics;
 pub mod open_loop_client;
+pub mod profile;
 pub mod protocol;
 pub mod serialize;
+pub mod socket;
+pub mod sweep;
+pub mod tcp_info;
 pub mod tcp_server;
+pub mod timeseries;
+pub mod trace;
 
 pub fn get_current_time_micros() -> u64 {
     use std::time::{SystemTime, UNIX_EPOCH};
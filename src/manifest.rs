@@ -0,0 +1,52 @@
+//! Run manifests: how a binary was invoked and the settings it actually ran with, so that runs
+//! can be compared after the fact.
+
+use crate::{get_current_time_micros, socket::SocketOptions};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// `key: value` lines describing a run, in the order they were added.
+#[derive(Debug, Clone)]
+pub struct Manifest(Vec<(String, String)>);
+
+impl Manifest {
+    /// A manifest that starts with this process's command line and the current time.
+    pub fn new() -> Self {
+        let mut manifest = Self(Vec::new());
+        manifest.add("command", std::env::args().collect::<Vec<_>>().join(" "));
+        manifest.add("started_us", get_current_time_micros());
+        manifest
+    }
+
+    pub fn add(&mut self, key: impl Into<String>, value: impl Display) {
+        self.0.push((key.into(), value.to_string()));
+    }
+
+    /// Add each of `options` as `<prefix>.<option>`.
+    pub fn add_socket_options(&mut self, prefix: &str, options: &SocketOptions) {
+        for (name, value) in options.entries() {
+            self.add(format!("{}.{}", prefix, name), value);
+        }
+    }
+
+    /// Write the manifest to `outdir/<name>.txt`.
+    pub fn write(&self, outdir: &Path, name: &str) -> Result<(), anyhow::Error> {
+        std::fs::create_dir_all(outdir)?;
+        let mut out = BufWriter::new(File::create(outdir.join(format!("{}.txt", name)))?);
+        for (key, value) in &self.0 {
+            writeln!(out, "{}: {}", key, value)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
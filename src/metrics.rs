@@ -0,0 +1,402 @@
+//! Latency and load summaries shared by the load generators.
+
+use crate::{
+    app::{WorkClass, WorkMix},
+    generator::GeneratorSummary,
+    serialize::LatencyRecord,
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    net::SocketAddr,
+    path::Path,
+    time::Duration,
+};
+
+/// Median, 95th and 99th percentile latency in microseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+impl Percentiles {
+    /// Percentiles of `values`, which are sorted in place. Returns `None` if `values` is empty.
+    pub fn from_unsorted(values: &mut [u64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_unstable();
+        Some(Self {
+            p50: percentile(values, 0.5) as f64,
+            p95: percentile(values, 0.95) as f64,
+            p99: percentile(values, 0.99) as f64,
+        })
+    }
+
+    /// Element-wise mean of per-thread percentiles.
+    pub fn mean(per_thread: &[Self]) -> Option<Self> {
+        if per_thread.is_empty() {
+            return None;
+        }
+        let n = per_thread.len() as f64;
+        Some(Self {
+            p50: per_thread.iter().map(|p| p.p50).sum::<f64>() / n,
+            p95: per_thread.iter().map(|p| p.p95).sum::<f64>() / n,
+            p99: per_thread.iter().map(|p| p.p99).sum::<f64>() / n,
+        })
+    }
+}
+
+/// Value at quantile `q` of an already-sorted, non-empty slice.
+pub fn percentile(sorted: &[u64], q: f64) -> u64 {
+    let idx = (sorted.len() as f64 * q) as usize;
+    sorted[idx.min(sorted.len() - 1)]
+}
+
+/// Aggregate results of one load generator run.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunSummary {
+    /// Requests the client tried to send.
+    pub attempted: u64,
+    /// Responses the client received and recorded a latency for.
+    pub completed: u64,
+    /// Failed sends, failed receives and failed responses.
+    pub errors: u64,
+    /// Attempted requests per second.
+    pub attempted_load: f64,
+    /// Completed requests per second, counting only requests sent inside the measured span.
+    pub achieved_load: f64,
+    /// Mean of the per-thread latency percentiles over the measured span, if any thread recorded
+    /// latencies inside it.
+    pub latency: Option<Percentiles>,
+    /// The server's busy work calibration in iterations/us, as reported in its responses.
+    pub server_iters_per_us: Option<f64>,
+    /// The client's measurements of itself.
+    pub generator: GeneratorSummary,
+}
+
+/// How a run's results are measured and reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MeasurementConfig {
+    pub window: MeasurementWindow,
+    /// If set, sample per-interval metrics at this interval and write them to
+    /// `outdir/timeseries.csv`.
+    pub sample_interval: Option<Duration>,
+    /// Print each per-interval sample as it is taken.
+    pub live: bool,
+    /// Write the requests an open loop run sent to `outdir/trace.txt`, for replay. Closed loop
+    /// runs have no schedule to record.
+    pub record_trace: bool,
+    /// If set, sample each connection's kernel TCP state at this interval and write the samples
+    /// to `outdir/tcp_info.csv`.
+    pub tcp_info_interval: Option<Duration>,
+}
+
+/// How much of the start and end of a run to exclude from statistics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MeasurementWindow {
+    pub warmup: Duration,
+    pub cooldown: Duration,
+}
+
+impl MeasurementWindow {
+    /// The measured span of a run that started at `run_start` (us since the epoch) and lasted
+    /// `runtime`.
+    pub fn span(&self, run_start: u64, runtime: Duration) -> MeasuredSpan {
+        let run_end = run_start + runtime.as_micros() as u64;
+        let start = run_start + self.warmup.as_micros() as u64;
+        let end = run_end.saturating_sub(self.cooldown.as_micros() as u64);
+        MeasuredSpan {
+            start,
+            end: end.max(start),
+        }
+    }
+}
+
+/// Absolute send-time bounds, in us since the epoch, of the requests that count toward
+/// statistics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeasuredSpan {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl MeasuredSpan {
+    pub fn contains(&self, record: &LatencyRecord) -> bool {
+        (self.start..self.end).contains(&record.send_timestamp)
+    }
+
+    pub fn as_secs_f64(&self) -> f64 {
+        (self.end - self.start) as f64 / 1e6
+    }
+}
+
+/// Write every thread's latency records to `outdir/latencies.csv`, flagging whether each falls
+/// inside `span` and which server answered it. Fan-out hop timings are left empty for other
+/// requests.
+pub fn write_latencies(
+    outdir: &Path,
+    per_thread: &[Vec<LatencyRecord>],
+    span: &MeasuredSpan,
+) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(outdir)?;
+    let mut out = BufWriter::new(File::create(outdir.join("latencies.csv"))?);
+    writeln!(
+        out,
+        "thread,send_timestamp_us,recv_timestamp_us,latency_us,server_processing_time_us,measured,work,hop_first_us,hop_quorum_us,hop_backend_us,backend"
+    )?;
+    for (thread, records) in per_thread.iter().enumerate() {
+        for r in records {
+            let hops = match r.fanout {
+                Some(t) => format!("{},{},{}", t.first_us, t.quorum_us, t.backend_us),
+                None => ",,".into(),
+            };
+            writeln!(
+                out,
+                "{},{},{},{},{},{},\"{}\",{},{}",
+                thread,
+                r.send_timestamp,
+                r.recv_timestamp,
+                r.latency,
+                r.server_processing_time,
+                span.contains(r) as u8,
+                r.work,
+                hops,
+                r.backend
+            )?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Latency of one class of a [`WorkMix`].
+#[derive(Debug, Clone, Copy)]
+pub struct ClassSummary {
+    pub class: WorkClass,
+    /// Responses recorded for this class inside the measured span.
+    pub completed: usize,
+    /// Percentiles over all threads' records of this class.
+    pub latency: Option<Percentiles>,
+}
+
+/// Summarize each class of `mix` over the records inside `span`.
+pub fn class_summaries(
+    mix: &WorkMix,
+    per_thread: &[Vec<LatencyRecord>],
+    span: &MeasuredSpan,
+) -> Vec<ClassSummary> {
+    mix.classes()
+        .enumerate()
+        .map(|(i, class)| {
+            let mut latency_values: Vec<u64> = per_thread
+                .iter()
+                .flatten()
+                .filter(|r| mix.class_of(&r.work) == Some(i) && span.contains(r))
+                .map(|r| r.latency)
+                .collect();
+            ClassSummary {
+                class,
+                completed: latency_values.len(),
+                latency: Percentiles::from_unsorted(&mut latency_values),
+            }
+        })
+        .collect()
+}
+
+/// Print per-class latencies and write them to `outdir/classes.csv`. Does nothing unless `mix`
+/// has more than one class.
+pub fn report_classes(
+    outdir: &Path,
+    mix: &WorkMix,
+    per_thread: &[Vec<LatencyRecord>],
+    span: &MeasuredSpan,
+) -> Result<(), anyhow::Error> {
+    if !mix.is_mixed() {
+        return Ok(());
+    }
+
+    let classes = class_summaries(mix, per_thread, span);
+    println!("\nPer-class Latencies:");
+    for c in &classes {
+        match c.latency {
+            Some(l) => println!(
+                "{}: {} requests, p50 {:.2} us, p95 {:.2} us, p99 {:.2} us",
+                c.class, c.completed, l.p50, l.p95, l.p99
+            ),
+            None => println!("{}: no requests", c.class),
+        }
+    }
+
+    std::fs::create_dir_all(outdir)?;
+    let mut out = BufWriter::new(File::create(outdir.join("classes.csv"))?);
+    writeln!(out, "work,completed,p50_us,p95_us,p99_us")?;
+    for c in &classes {
+        let latency = match c.latency {
+            Some(l) => format!("{:.1},{:.1},{:.1}", l.p50, l.p95, l.p99),
+            None => ",,".into(),
+        };
+        writeln!(out, "\"{}\",{},{}", c.class, c.completed, latency)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Print each server's share of the requests inside `span` and their latencies, and write them
+/// to `outdir/backends.csv`. `addrs` are the servers in the order records index them. Does
+/// nothing unless there is more than one server.
+pub fn report_backends(
+    outdir: &Path,
+    addrs: &[SocketAddr],
+    per_thread: &[Vec<LatencyRecord>],
+    span: &MeasuredSpan,
+) -> Result<(), anyhow::Error> {
+    if addrs.len() < 2 {
+        return Ok(());
+    }
+
+    let mut per_backend = vec![Vec::new(); addrs.len()];
+    for r in per_thread.iter().flatten().filter(|r| span.contains(r)) {
+        per_backend[r.backend].push(r.latency);
+    }
+    let total = per_backend.iter().map(Vec::len).sum::<usize>().max(1);
+    let backends: Vec<_> = per_backend
+        .into_iter()
+        .map(|mut values| (values.len(), Percentiles::from_unsorted(&mut values)))
+        .collect();
+
+    println!("\nPer-backend Latencies:");
+    for (addr, (completed, latency)) in addrs.iter().zip(&backends) {
+        let share = 100.0 * *completed as f64 / total as f64;
+        match latency {
+            Some(l) => println!(
+                "{}: {} requests ({:.1}%), p50 {:.2} us, p95 {:.2} us, p99 {:.2} us",
+                addr, completed, share, l.p50, l.p95, l.p99
+            ),
+            None => println!("{}: no requests", addr),
+        }
+    }
+
+    std::fs::create_dir_all(outdir)?;
+    let mut out = BufWriter::new(File::create(outdir.join("backends.csv"))?);
+    writeln!(out, "backend,addr,completed,p50_us,p95_us,p99_us")?;
+    for (i, (addr, (completed, latency))) in addrs.iter().zip(&backends).enumerate() {
+        let latency = match latency {
+            Some(l) => format!("{:.1},{:.1},{:.1}", l.p50, l.p95, l.p99),
+            None => ",,".into(),
+        };
+        writeln!(out, "{},{},{},{}", i, addr, completed, latency)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Print percentiles of the downstream hop timings of the fan-out requests inside `span`. Does
+/// nothing if there are none.
+pub fn report_fanout(per_thread: &[Vec<LatencyRecord>], span: &MeasuredSpan) {
+    let timings: Vec<_> = per_thread
+        .iter()
+        .flatten()
+        .filter(|r| span.contains(r))
+        .filter_map(|r| r.fanout)
+        .collect();
+    if timings.is_empty() {
+        return;
+    }
+
+    println!("\nFan-out Hops ({} requests):", timings.len());
+    let hops = [
+        (
+            "First response",
+            timings.iter().map(|t| t.first_us).collect::<Vec<_>>(),
+        ),
+        ("Quorum", timings.iter().map(|t| t.quorum_us).collect()),
+        (
+            "Backend processing",
+            timings.iter().map(|t| t.backend_us).collect(),
+        ),
+    ];
+    for (name, mut values) in hops {
+        let p = Percentiles::from_unsorted(&mut values).unwrap();
+        println!(
+            "{}: p50 {:.2} us, p95 {:.2} us, p99 {:.2} us",
+            name, p.p50, p.p95, p.p99
+        );
+    }
+}
+
+/// Mean of `samples` and the half-width of its 95% confidence interval, using Student's t
+/// distribution. The half-width is zero for fewer than two samples.
+pub fn mean_ci95(samples: &[f64]) -> (f64, f64) {
+    // Two-sided 95% critical values of Student's t for 1..=30 degrees of freedom.
+    const T_95: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+
+    let n = samples.len();
+    if n == 0 {
+        return (0.0, 0.0);
+    }
+    let mean = samples.iter().sum::<f64>() / n as f64;
+    if n < 2 {
+        return (mean, 0.0);
+    }
+    let var = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+    let t = T_95.get(n - 2).copied().unwrap_or(1.96);
+    (mean, t * (var / n as f64).sqrt())
+}
+
+#[cfg(test)]
+mod t {
+    use super::{mean_ci95, MeasuredSpan, MeasurementWindow, Percentiles};
+    use std::time::Duration;
+
+    #[test]
+    fn percentiles_of_unsorted() {
+        let mut values: Vec<u64> = (1..=100).rev().collect();
+        let p = Percentiles::from_unsorted(&mut values).expect("nonempty");
+        assert_eq!(p.p50, 51.0);
+        assert_eq!(p.p95, 96.0);
+        assert_eq!(p.p99, 100.0);
+        assert!(Percentiles::from_unsorted(&mut []).is_none());
+    }
+
+    #[test]
+    fn ci95_of_samples() {
+        assert_eq!(mean_ci95(&[]), (0.0, 0.0));
+        assert_eq!(mean_ci95(&[5.0]), (5.0, 0.0));
+        let (mean, half) = mean_ci95(&[9.0, 10.0, 11.0]);
+        assert_eq!(mean, 10.0);
+        assert!((half - 4.303 / 3f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn window_span() {
+        let window = MeasurementWindow {
+            warmup: Duration::from_millis(100),
+            cooldown: Duration::from_millis(200),
+        };
+        let span = window.span(1_000_000, Duration::from_secs(1));
+        assert_eq!(
+            span,
+            MeasuredSpan {
+                start: 1_100_000,
+                end: 1_800_000
+            }
+        );
+        assert_eq!(span.as_secs_f64(), 0.7);
+
+        // A window longer than the run measures nothing rather than underflowing.
+        let span = window.span(1_000_000, Duration::from_millis(250));
+        assert_eq!(span.start, span.end);
+        assert_eq!(span.as_secs_f64(), 0.0);
+        assert_eq!(
+            MeasurementWindow::default().span(5, Duration::from_micros(10)),
+            MeasuredSpan { start: 5, end: 15 }
+        );
+    }
+}
@@ -1,34 +1,44 @@
 use crate::{
+    affinity,
+    balance::{Balancer, Servers},
+    engine::{self, Backend, Clients, Counters, Requests, Sockets, ThreadOutput},
+    generator::{self, ProcessMonitor, Role, ThreadMonitor, ThreadUsage},
     get_current_time_micros,
+    metrics::{
+        report_backends, report_classes, report_fanout, write_latencies, MeasurementConfig,
+        Percentiles, RunSummary,
+    },
+    profile::LoadProfile,
     protocol::{work_request::ClientWorkPacketConn, work_response::ServerWorkPacketConn},
     serialize::{ClientWorkPacket, LatencyRecord},
+    tcp_info::{self, TcpInfoSample, TcpInfoSampler},
+    timeseries::{IntervalRecorder, Sampler},
+    trace::{Trace, TraceEntry},
 };
 use minstant::Instant;
 use std::{
-    fs::File,
-    io::{BufWriter, Write},
-    net::{SocketAddrV4, TcpStream},
+    net::TcpStream,
     path::PathBuf,
     sync::{
-        atomic::{AtomicBool, AtomicU64, Ordering},
+        atomic::{AtomicBool, Ordering},
         Arc,
     },
     thread::{self, JoinHandle},
     time::Duration,
 };
 
-use crate::app::Work;
+use crate::app::{Work, WorkMix};
 
 // Struct to track attempted load
 struct AttemptedLoadTracker {
-    packets_sent: Arc<AtomicU64>,
+    counters: Arc<Counters>,
     start_time: Instant,
 }
 
 impl AttemptedLoadTracker {
-    fn new(packets_sent: Arc<AtomicU64>) -> Self {
+    fn new(counters: Arc<Counters>) -> Self {
         AttemptedLoadTracker {
-            packets_sent,
+            counters,
             start_time: Instant::now(),
         }
     }
@@ -36,55 +46,174 @@ impl AttemptedLoadTracker {
     fn get_attempted_load(&self) -> f64 {
         let elapsed_secs = self.start_time.elapsed().as_secs_f64();
         if elapsed_secs > 0.0 {
-            self.packets_sent.load(Ordering::SeqCst) as f64 / elapsed_secs
+            self.counters.sent.load(Ordering::SeqCst) as f64 / elapsed_secs
         } else {
             0.0
         }
     }
 }
 
+/// Interarrival time each of `num_connections` connections must use so that together they offer
+/// `rate` requests per second. Rounded to whole nanoseconds, but never down to zero.
+pub fn interarrival_for_rate(rate: u64, num_connections: usize) -> Duration {
+    Duration::from_secs_f64(num_connections as f64 / rate as f64).max(Duration::from_nanos(1))
+}
+
+// What one connection sends, and when
+enum Schedule {
+    // A request sampled from `work` every `interarrival` from when the connection starts sending,
+    // until `runtime` has passed
+    Paced {
+        interarrival: Duration,
+        runtime: Duration,
+        work: WorkMix,
+    },
+    // Each entry at its arrival time after the start of the run
+    Trace(Vec<TraceEntry>),
+    // A request sampled from `work` at this connection's share of `profile`'s rate, until
+    // `runtime` has passed
+    Profile {
+        profile: LoadProfile,
+        num_connections: usize,
+        runtime: Duration,
+        work: WorkMix,
+    },
+}
+
+impl Schedule {
+    // Send time, work and payload size of each request
+    fn requests(
+        self,
+        run_start: Instant,
+    ) -> Box<dyn Iterator<Item = (Instant, Work, Option<usize>)> + Send> {
+        match self {
+            Self::Paced {
+                interarrival,
+                runtime,
+                work,
+            } => {
+                let start = Instant::now();
+                let send_times = std::iter::successors(Some(start), move |t| Some(*t + interarrival));
+                Box::new(
+                    send_times
+                        .take_while(move |_| start.elapsed() < runtime)
+                        .map(move |t| (t, work.sample(), None)),
+                )
+            }
+            Self::Trace(entries) => Box::new(
+                entries
+                    .into_iter()
+                    .map(move |e| (run_start + e.arrival, e.work, e.payload)),
+            ),
+            Self::Profile {
+                profile,
+                num_connections,
+                runtime,
+                work,
+            } => Box::new(
+                profile
+                    .send_times(runtime, num_connections)
+                    .map(move |t| (run_start + t, work.sample(), None)),
+            ),
+        }
+    }
+}
+
+// Send every request of `schedule` over whichever of `send_streams` `balancer` routes it to,
+// returning what was sent if `record` is set
 fn client_open_loop(
-    send_stream: TcpStream,
-    thread_start_time: Instant,
-    thread_delay: Duration,
-    runtime: Duration,
-    packets_sent: Arc<AtomicU64>,
-    work: Work,
-) {
-    let mut conn = ClientWorkPacketConn::new(&send_stream);
-    let mut next_send_time = thread_start_time;
-
-    while thread_start_time.elapsed() < runtime {
-        let work_packet = ClientWorkPacket::new(get_current_time_micros(), work);
-        if conn.send_work_msg(work_packet).is_ok() {
-            packets_sent.fetch_add(1, Ordering::SeqCst);
-            next_send_time += thread_delay;
-            // Use spin lock instead of thread::sleep
-            while Instant::now() < next_send_time {
-                std::hint::spin_loop();
+    send_streams: Sockets,
+    balancer: Arc<Balancer>,
+    schedule: Schedule,
+    run_start: Instant,
+    counters: Arc<Counters>,
+    recorder: Arc<IntervalRecorder>,
+    record: bool,
+) -> (Vec<TraceEntry>, ThreadUsage) {
+    let mut conns: Vec<_> = send_streams
+        .iter()
+        .map(|(_, stream)| ClientWorkPacketConn::new(stream))
+        .collect();
+    let mut sent = Vec::new();
+    let mut monitor = ThreadMonitor::start(Role::Send);
+
+    for (send_time, work, payload) in schedule.requests(run_start) {
+        // Use spin lock instead of thread::sleep
+        while Instant::now() < send_time {
+            std::hint::spin_loop();
+        }
+        monitor.record_slip(Instant::now().saturating_duration_since(send_time));
+        let mut work_packet = ClientWorkPacket::new(get_current_time_micros(), work);
+        if let Some(len) = payload {
+            work_packet = work_packet.with_payload(len);
+        }
+        let k = balancer.route(&work);
+        let (backend, send_stream) = &send_streams[k];
+        // Counted against the server before it's written, since its response can arrive before
+        // the write returns.
+        balancer.sent(*backend);
+        if conns[k].send_work_msg(work_packet).is_ok() {
+            balancer.options().message_sent(send_stream).ok();
+            counters.sent.fetch_add(1, Ordering::SeqCst);
+            recorder.record_sent();
+            if record {
+                sent.push(TraceEntry {
+                    arrival: send_time.saturating_duration_since(run_start),
+                    work,
+                    payload,
+                });
             }
         } else {
+            balancer.completed(*backend, 1);
+            counters.record_error();
+            recorder.record_error();
             break;
         }
     }
+    (sent, monitor.finish())
 }
 
+// What a receiving thread collects: latencies, how it fared, and its socket's TCP state
+type RecvOutput = (Vec<LatencyRecord>, ThreadUsage, Vec<TcpInfoSample>);
+
+// Receive responses from server `backend` until `receiver_complete` is set, sampling the TCP
+// state of socket `id` every `tcp_info_interval` if set
 fn client_recv_loop(
-    recv_stream: TcpStream,
+    (backend, recv_stream): (usize, TcpStream),
+    id: usize,
+    balancer: Arc<Balancer>,
     receiver_complete: Arc<AtomicBool>,
-) -> Vec<LatencyRecord> {
+    counters: Arc<Counters>,
+    recorder: Arc<IntervalRecorder>,
+    tcp_info_interval: Option<Duration>,
+) -> RecvOutput {
     let mut conn = ServerWorkPacketConn::new(&recv_stream);
     let mut latencies = Vec::new();
+    let mut monitor = ThreadMonitor::start(Role::Recv);
+    let mut tcp_info = TcpInfoSampler::new(tcp_info_interval);
 
     // Set a reasonable timeout
     recv_stream.set_read_timeout(Some(Duration::from_secs(5))).ok();
     
     while !receiver_complete.load(Ordering::SeqCst) {
+        monitor.record_backlog(generator::queued_bytes(&recv_stream));
+        tcp_info.sample_if_due(id, &recv_stream);
         match conn.recv_work_msg() {
             Ok(server_work_packet) => {
+                balancer.options().message_received(&recv_stream).ok();
+                balancer.completed(backend, 1);
                 let recv_timestamp = get_current_time_micros();
-                if let Some(latency_record) = server_work_packet.calculate_latency(recv_timestamp) {
-                    latencies.push(latency_record);
+                counters.record_server_iters_per_us(server_work_packet.busy_work_iters_per_us());
+                match server_work_packet.calculate_latency(recv_timestamp) {
+                    Some(mut latency_record) => {
+                        latency_record.backend = backend;
+                        recorder.record_received(latency_record.latency);
+                        latencies.push(latency_record);
+                    }
+                    None => {
+                        counters.record_error();
+                        recorder.record_error();
+                    }
                 }
             }
             Err(e) => {
@@ -101,80 +230,264 @@ fn client_recv_loop(
                        io_err.kind() == std::io::ErrorKind::ConnectionAborted ||
                        io_err.kind() == std::io::ErrorKind::BrokenPipe {
                         eprintln!("Connection error: {:?}", io_err);
+                        counters.record_error();
+                        recorder.record_error();
                         break;
                     }
                 }
                 
                 // For other errors, log and continue collecting
                 eprintln!("Error receiving work packet: {:?}", e);
+                counters.record_error();
+                recorder.record_error();
             }
         }
     }
 
-    latencies
+    (latencies, monitor.finish(), tcp_info.take())
 }
 
-fn init_client(
-    server_addr: SocketAddrV4,
-    thread_delay: Duration,
-    runtime: Duration,
-    work: Work,
-) -> (JoinHandle<Vec<LatencyRecord>>, Arc<AtomicU64>) {
-    let stream = TcpStream::connect(&server_addr).expect("Couldn't connect to server");
-    stream.set_nodelay(true).expect("set_nodelay call failed");
-    let thread_start_time = Instant::now();
+// Threads serving one blocking connection, with a receiving thread for each of its sockets, or
+// one engine thread serving many connections
+enum ClientHandles {
+    Threads {
+        send: JoinHandle<(Vec<TraceEntry>, ThreadUsage)>,
+        recv: Vec<JoinHandle<RecvOutput>>,
+    },
+    Engine(JoinHandle<ThreadOutput>),
+}
 
-    let sent = Arc::new(AtomicU64::new(0));
+impl ClientHandles {
+    // What was sent, the latencies of what was received, how each thread fared, and the sampled
+    // TCP state of the connections
+    fn join(
+        self,
+    ) -> (
+        Vec<TraceEntry>,
+        Vec<LatencyRecord>,
+        Vec<ThreadUsage>,
+        Vec<TcpInfoSample>,
+    ) {
+        match self {
+            Self::Threads { send, recv } => {
+                let (sent, send_usage) = send.join().unwrap();
+                let (mut latencies, mut usages, mut tcp_info) = (Vec::new(), vec![send_usage], Vec::new());
+                for handle in recv {
+                    let (socket_latencies, recv_usage, socket_tcp_info) = handle.join().unwrap();
+                    latencies.extend(socket_latencies);
+                    usages.push(recv_usage);
+                    tcp_info.extend(socket_tcp_info);
+                }
+                (sent, latencies, usages, tcp_info)
+            }
+            Self::Engine(handle) => {
+                let output = handle.join().unwrap();
+                (output.sent, output.latencies, vec![output.usage], output.tcp_info)
+            }
+        }
+    }
+}
+
+fn init_client(
+    streams: Sockets,
+    id: usize,
+    balancer: Arc<Balancer>,
+    schedule: Schedule,
+    run_start: Instant,
+    measurement: &MeasurementConfig,
+    recorder: Arc<IntervalRecorder>,
+) -> (ClientHandles, Arc<Counters>) {
+    let record = measurement.record_trace;
+    let tcp_info_interval = measurement.tcp_info_interval;
+    let counters = Arc::new(Counters::default());
     let done = Arc::new(AtomicBool::new(false));
 
-    {
-        let stream = stream.try_clone().expect("Failed to clone stream");
-        let sent = sent.clone();
+    let send_handle = {
+        let streams = streams
+            .iter()
+            .map(|(backend, stream)| (*backend, stream.try_clone().expect("Failed to clone stream")))
+            .collect();
+        let balancer = balancer.clone();
+        let counters = counters.clone();
+        let recorder = recorder.clone();
         let done = done.clone();
-        let _ = thread::spawn(move || {
-            client_open_loop(stream, thread_start_time, thread_delay, runtime, sent, work);
+        thread::spawn(move || {
+            affinity::place_thread();
+            let sent =
+                client_open_loop(streams, balancer, schedule, run_start, counters, recorder, record);
             done.store(true, Ordering::SeqCst);
-        });
-    }
-
-    let recv_handle = {
-        let stream = stream.try_clone().expect("Failed to clone stream");
-        let done = done.clone();
-        thread::spawn(move || client_recv_loop(stream, done))
+            sent
+        })
     };
 
-    (recv_handle, sent)
+    let per_conn = streams.len();
+    let recv_handles = streams
+        .into_iter()
+        .enumerate()
+        .map(|(k, socket)| {
+            let balancer = balancer.clone();
+            let done = done.clone();
+            let counters = counters.clone();
+            let recorder = recorder.clone();
+            let id = id * per_conn + k;
+            thread::spawn(move || {
+                affinity::place_thread();
+                client_recv_loop(socket, id, balancer, done, counters, recorder, tcp_info_interval)
+            })
+        })
+        .collect();
+
+    (
+        ClientHandles::Threads {
+            send: send_handle,
+            recv: recv_handles,
+        },
+        counters,
+    )
 }
 
+/// Send `work` from each of `clients`' connections, each sending one request every
+/// `interarrival`, for `runtime`.
 pub fn run(
-    server_addr: SocketAddrV4,
-    num_threads: usize,
+    servers: &Servers,
+    clients: Clients,
     interarrival: Duration,
     runtime: Duration,
-    work: Work,
+    work: WorkMix,
+    measurement: MeasurementConfig,
+    outdir: PathBuf,
+) -> RunSummary {
+    let schedules = (0..clients.connections)
+        .map(|_| Schedule::Paced {
+            interarrival,
+            runtime,
+            work: work.clone(),
+        })
+        .collect();
+    run_schedules(servers, clients, schedules, runtime, Some(&work), measurement, outdir)
+}
+
+/// Replay the requests of `trace` that arrive within `runtime`, dealt round robin over
+/// `clients`' connections.
+pub fn replay(
+    servers: &Servers,
+    clients: Clients,
+    trace: &Trace,
+    runtime: Duration,
+    measurement: MeasurementConfig,
     outdir: PathBuf,
-) {    
+) -> RunSummary {
+    let schedules = trace
+        .split(runtime, clients.connections)
+        .into_iter()
+        .map(Schedule::Trace)
+        .collect();
+    run_schedules(servers, clients, schedules, runtime, None, measurement, outdir)
+}
+
+/// Send `work` from `clients`' connections at a total rate that follows `profile`, for
+/// `runtime`.
+pub fn run_profile(
+    servers: &Servers,
+    clients: Clients,
+    profile: &LoadProfile,
+    runtime: Duration,
+    work: WorkMix,
+    measurement: MeasurementConfig,
+    outdir: PathBuf,
+) -> RunSummary {
+    let num_connections = clients.connections;
+    let schedules = (0..num_connections)
+        .map(|_| Schedule::Profile {
+            profile: profile.clone(),
+            num_connections,
+            runtime,
+            work: work.clone(),
+        })
+        .collect();
+    run_schedules(servers, clients, schedules, runtime, Some(&work), measurement, outdir)
+}
+
+fn run_schedules(
+    servers: &Servers,
+    clients: Clients,
+    schedules: Vec<Schedule>,
+    runtime: Duration,
+    work: Option<&WorkMix>,
+    measurement: MeasurementConfig,
+    outdir: PathBuf,
+) -> RunSummary {
+    // Connect first so that connection setup doesn't delay the start of any schedule.
+    if clients.backend != Backend::Threads {
+        engine::raise_fd_limit();
+    }
+    let balancer = Arc::new(Balancer::new(servers, clients.sockets));
+    let streams: Vec<_> = (0..schedules.len())
+        .map(|id| balancer.connect(id).expect("Couldn't connect to server"))
+        .collect();
+    let run_start = Instant::now();
+    let process = ProcessMonitor::start();
+    let span = measurement.window.span(get_current_time_micros(), runtime);
+    let sampler = Sampler::start(measurement.sample_interval, measurement.live);
+
     // Initialize clients and collect handles and packet counters
     let mut join_handles = Vec::new();
-    let mut packet_counters = Vec::new();
+    let mut conn_counters = Vec::new();
     
-    for i in 0..num_threads {
-        let (handle, packets_sent) = init_client(server_addr, interarrival, runtime, work);
-        join_handles.push(handle);
-        packet_counters.push(packets_sent);
+    if clients.backend != Backend::Threads {
+        let conns = streams
+            .into_iter()
+            .zip(schedules)
+            .map(|(stream, schedule)| {
+                (stream, Box::new(schedule.requests(run_start)) as Box<dyn Requests>)
+            })
+            .collect();
+        for (handle, counters) in engine::spawn(
+            balancer,
+            clients,
+            conns,
+            run_start,
+            sampler.recorder(),
+            &measurement,
+        ) {
+            join_handles.push(ClientHandles::Engine(handle));
+            conn_counters.push(counters);
+        }
+    } else {
+        for (id, (stream, schedule)) in streams.into_iter().zip(schedules).enumerate() {
+            let (handles, counters) = init_client(
+                stream,
+                id,
+                balancer.clone(),
+                schedule,
+                run_start,
+                &measurement,
+                sampler.recorder(),
+            );
+            join_handles.push(handles);
+            conn_counters.push(counters);
+        }
     }
+    let num_threads = join_handles.len();
 
     // Create load trackers for each thread
-    let load_trackers: Vec<_> = packet_counters.iter()
-        .map(|counter| AttemptedLoadTracker::new(counter.clone()))
+    let load_trackers: Vec<_> = conn_counters.iter()
+        .map(|counters| AttemptedLoadTracker::new(counters.clone()))
         .collect();
 
     // Collect latencies
     let mut request_latencies: Vec<Vec<LatencyRecord>> = Vec::new();
-    for handle in join_handles {
-        let thread_latencies = handle.join().unwrap();
+    let mut sent = Vec::new();
+    let mut usages = Vec::new();
+    let mut tcp_samples = Vec::new();
+    for handles in join_handles {
+        let (thread_sent, thread_latencies, thread_usages, thread_tcp_info) = handles.join();
+        sent.extend(thread_sent);
         request_latencies.push(thread_latencies);
+        usages.extend(thread_usages);
+        tcp_samples.extend(thread_tcp_info);
     }
+    let process_cpu = process.finish();
 
     // Calculate and print load metrics
     let mut thread_loads = Vec::new();
@@ -184,7 +497,7 @@ pub fn run(
         let attempted_load = tracker.get_attempted_load();
         thread_loads.push(attempted_load);
         
-        let packets = tracker.packets_sent.load(Ordering::SeqCst);
+        let packets = tracker.counters.sent.load(Ordering::SeqCst);
         total_packets += packets;
         
         println!("Thread {} latency count: {}", i, request_latencies[i].len());
@@ -202,6 +515,21 @@ pub fn run(
     } else {
         0.0
     };
+    let total_completed = request_latencies.iter().map(Vec::len).sum::<usize>() as u64;
+    let total_measured = request_latencies
+        .iter()
+        .flatten()
+        .filter(|record| span.contains(record))
+        .count();
+    let aggregate_achieved_load = if span.as_secs_f64() > 0.0 {
+        total_measured as f64 / span.as_secs_f64()
+    } else {
+        0.0
+    };
+    let total_errors = conn_counters
+        .iter()
+        .map(|counters| counters.errors.load(Ordering::SeqCst))
+        .sum::<u64>();
     
     println!("\nAggregate Metrics:");
     println!("Total packets sent: {}", total_packets);
@@ -212,45 +540,86 @@ pub fn run(
              } else { 
                  0.0 
              });
+    println!("Achieved load: {:.2} req/s", aggregate_achieved_load);
+    println!("Errors: {}", total_errors);
+    let server_iters_per_us = conn_counters
+        .iter()
+        .find_map(|counters| counters.server_iters_per_us());
+    if let Some(factor) = server_iters_per_us {
+        println!("Server busy work calibration: {:.2} iterations/us", factor);
+    }
     
-    // Calculate latency percentiles if we have enough data
-    if request_latencies.iter().any(|latencies| !latencies.is_empty()) {
-        // Define warm-up constant to ignore initial records for more accurate measurements
-        const WARM_UP: usize = 50;
-        
-        let mut median_latencies = Vec::new();
-        let mut p95_latencies = Vec::new();
-        let mut p99_latencies = Vec::new();
-        
-        for thread_latencies in &request_latencies {
-            if thread_latencies.len() > WARM_UP {
-                let mut latency_values: Vec<u64> = thread_latencies.iter()
-                    .skip(WARM_UP)  // Skip the first WARM_UP records
-                    .map(|record| record.latency)
-                    .collect();
-                latency_values.sort();
-                
-                if !latency_values.is_empty() {
-                    let median_idx = latency_values.len() / 2;
-                    let p95_idx = (latency_values.len() as f64 * 0.95) as usize;
-                    let p99_idx = (latency_values.len() as f64 * 0.99) as usize;
-                    
-                    median_latencies.push(latency_values[median_idx]);
-                    p95_latencies.push(latency_values[p95_idx]);
-                    p99_latencies.push(latency_values[p99_idx]);
-                }
-            }
+    // Calculate latency percentiles, ignoring warm-up and cool-down records
+    let mut thread_percentiles = Vec::new();
+    for thread_latencies in &request_latencies {
+        let mut latency_values: Vec<u64> = thread_latencies.iter()
+            .filter(|record| span.contains(record))
+            .map(|record| record.latency)
+            .collect();
+        thread_percentiles.extend(Percentiles::from_unsorted(&mut latency_values));
+    }
+
+    if let Err(e) = sampler.finish(&outdir) {
+        eprintln!("Failed to write time series: {:?}", e);
+    }
+    if let Err(e) = write_latencies(&outdir, &request_latencies, &span) {
+        eprintln!("Failed to write latency records: {:?}", e);
+    }
+    if let Some(work) = work {
+        if let Err(e) = report_classes(&outdir, work, &request_latencies, &span) {
+            eprintln!("Failed to write per-class latencies: {:?}", e);
         }
-        
-        if !median_latencies.is_empty() {
-            let mean_median_latency = median_latencies.iter().sum::<u64>() as f64 / median_latencies.len() as f64;
-            let mean_p95_latency = p95_latencies.iter().sum::<u64>() as f64 / p95_latencies.len() as f64;
-            let mean_p99_latency = p99_latencies.iter().sum::<u64>() as f64 / p99_latencies.len() as f64;
-            
-            println!("\nMean Aggregated Latencies:");
-            println!("Median latency: {:.2} us", mean_median_latency);
-            println!("95th percentile latency: {:.2} us", mean_p95_latency);
-            println!("99th percentile latency: {:.2} us", mean_p99_latency);
+    }
+    if measurement.record_trace {
+        if let Err(e) = Trace::new(sent).write(&outdir.join("trace.txt")) {
+            eprintln!("Failed to write trace: {:?}", e);
+        }
+    }
+    report_fanout(&request_latencies, &span);
+    if let Err(e) = report_backends(&outdir, &servers.addrs, &request_latencies, &span) {
+        eprintln!("Failed to write per-backend latencies: {:?}", e);
+    }
+    if let Err(e) = tcp_info::report(&outdir, "tcp_info", &tcp_samples) {
+        eprintln!("Failed to write TCP info samples: {:?}", e);
+    }
+
+    let latency = Percentiles::mean(&thread_percentiles);
+    if let Some(latency) = latency {
+        println!("\nMean Aggregated Latencies:");
+        println!("Median latency: {:.2} us", latency.p50);
+        println!("95th percentile latency: {:.2} us", latency.p95);
+        println!("99th percentile latency: {:.2} us", latency.p99);
+    }
+    let generator = generator::report(&outdir, &usages, process_cpu, true);
+
+    RunSummary {
+        attempted: total_packets,
+        completed: total_completed,
+        errors: total_errors,
+        attempted_load: aggregate_attempted_load,
+        achieved_load: aggregate_achieved_load,
+        latency,
+        server_iters_per_us,
+        generator,
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::interarrival_for_rate;
+    use std::time::Duration;
+
+    #[test]
+    fn interarrival_splits_rate() {
+        let offered = |rate, conns| conns as f64 / interarrival_for_rate(rate, conns).as_secs_f64();
+        assert_eq!(interarrival_for_rate(1000, 1), Duration::from_millis(1));
+        assert_eq!(interarrival_for_rate(1000, 4), Duration::from_millis(4));
+        // Rates that don't divide evenly, or that need sub-microsecond gaps, round to within a
+        // fraction of a percent.
+        for (rate, conns) in [(1000, 3), (7, 2), (3_000_000, 4), (250_000_000, 16)] {
+            let error = (offered(rate, conns) - rate as f64).abs() / rate as f64;
+            assert!(error < 1e-3, "{} req/s over {}: off by {}", rate, conns, error);
         }
+        assert_eq!(interarrival_for_rate(u64::MAX, 1), Duration::from_nanos(1));
     }
 }
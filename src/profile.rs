@@ -0,0 +1,399 @@
+//! Time-varying open loop load profiles.
+
+use std::{fs, num::ParseFloatError, num::ParseIntError, path::Path, time::Duration};
+
+/// How the total request rate varies over one [`Segment`] of a [`LoadProfile`].
+///
+/// Implements [`FromStr`](std::str::FromStr). Rates are total req/s across all connections.
+/// Options are:
+/// - `const:[rate]`
+/// - `ramp:[from],[to]` (linear over the segment)
+/// - `onoff:[rate],[burst_ms],[duty]` (bursts of `rate` for `burst_ms`, idle for the rest of
+///   each period; `duty` is the f64 fraction of each period spent bursting, in (0, 1])
+/// - `steps:[rate]/[secs],[rate]/[secs],...` (the last rate holds for the rest of the segment)
+/// - `sine:[mean],[amplitude],[period_secs]`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Const(u64),
+    Ramp {
+        from: u64,
+        to: u64,
+    },
+    OnOff {
+        rate: u64,
+        burst: Duration,
+        duty: f64,
+    },
+    Steps(Vec<(u64, Duration)>),
+    Sine {
+        mean: u64,
+        amplitude: u64,
+        period: Duration,
+    },
+}
+
+impl Shape {
+    /// Rate `t` into a segment lasting `len`.
+    fn rate(&self, t: Duration, len: Duration) -> f64 {
+        match self {
+            Self::Const(rate) => *rate as f64,
+            Self::Ramp { from, to } => {
+                let frac = if len.is_zero() {
+                    1.0
+                } else {
+                    (t.as_secs_f64() / len.as_secs_f64()).min(1.0)
+                };
+                *from as f64 + (*to as f64 - *from as f64) * frac
+            }
+            Self::OnOff { rate, burst, duty } => {
+                let period = burst.as_secs_f64() / duty;
+                if t.as_secs_f64() % period < burst.as_secs_f64() {
+                    *rate as f64
+                } else {
+                    0.0
+                }
+            }
+            Self::Steps(steps) => {
+                let mut end = Duration::ZERO;
+                for (rate, step_len) in steps {
+                    end += *step_len;
+                    if t < end {
+                        return *rate as f64;
+                    }
+                }
+                steps.last().map_or(0.0, |(rate, _)| *rate as f64)
+            }
+            Self::Sine {
+                mean,
+                amplitude,
+                period,
+            } => {
+                let phase = std::f64::consts::TAU * t.as_secs_f64() / period.as_secs_f64();
+                (*mean as f64 + *amplitude as f64 * phase.sin()).max(0.0)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Shape {
+    type Err = ProfileParseErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || ProfileParseErr::UnknownFmt(s.to_owned());
+        let (variant, params) = s.split_once(':').ok_or_else(unknown)?;
+        let params: Vec<_> = params.split(',').collect();
+        match (variant, &params[..]) {
+            ("const", [rate]) => Ok(Self::Const(rate.parse()?)),
+            ("ramp", [from, to]) => Ok(Self::Ramp {
+                from: from.parse()?,
+                to: to.parse()?,
+            }),
+            ("onoff", [rate, burst_ms, duty]) => {
+                let duty: f64 = duty.parse()?;
+                if !(duty > 0.0 && duty <= 1.0) {
+                    return Err(ProfileParseErr::InvalidParam(
+                        "onoff duty must be in (0, 1]",
+                    ));
+                }
+                let burst = Duration::from_millis(burst_ms.parse()?);
+                if burst.is_zero() {
+                    return Err(ProfileParseErr::InvalidParam("onoff burst must be nonzero"));
+                }
+                Ok(Self::OnOff {
+                    rate: rate.parse()?,
+                    burst,
+                    duty,
+                })
+            }
+            ("steps", steps) => steps
+                .iter()
+                .map(|step| {
+                    let (rate, secs) = step.split_once('/').ok_or_else(unknown)?;
+                    Ok((rate.parse()?, parse_secs(secs)?))
+                })
+                .collect::<Result<_, _>>()
+                .map(Self::Steps),
+            ("sine", [mean, amplitude, period_secs]) => {
+                let period: f64 = period_secs.parse()?;
+                if !(period > 0.0 && period.is_finite()) {
+                    return Err(ProfileParseErr::InvalidParam(
+                        "sine period must be positive",
+                    ));
+                }
+                Ok(Self::Sine {
+                    mean: mean.parse()?,
+                    amplitude: amplitude.parse()?,
+                    period: Duration::from_secs_f64(period),
+                })
+            }
+            _ => Err(unknown()),
+        }
+    }
+}
+
+impl std::fmt::Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Const(rate) => write!(f, "const:{}", rate),
+            Self::Ramp { from, to } => write!(f, "ramp:{},{}", from, to),
+            Self::OnOff { rate, burst, duty } => {
+                write!(f, "onoff:{},{},{}", rate, burst.as_millis(), duty)
+            }
+            Self::Steps(steps) => {
+                write!(f, "steps:")?;
+                for (i, (rate, len)) in steps.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}/{}", rate, len.as_secs_f64())?;
+                }
+                Ok(())
+            }
+            Self::Sine {
+                mean,
+                amplitude,
+                period,
+            } => write!(f, "sine:{},{},{}", mean, amplitude, period.as_secs_f64()),
+        }
+    }
+}
+
+fn parse_secs(secs: &str) -> Result<Duration, ProfileParseErr> {
+    Duration::try_from_secs_f64(secs.parse()?)
+        .map_err(|_| ProfileParseErr::InvalidParam("durations must be non-negative"))
+}
+
+/// Things that can go wrong when parsing a [`Shape`] or a profile file.
+#[derive(Debug)]
+pub enum ProfileParseErr {
+    /// Not one of the [`Shape`] formats, or not a `[secs] [shape]` profile file line.
+    UnknownFmt(String),
+    U64Parse(ParseIntError),
+    F64Parse(ParseFloatError),
+    /// A parameter was out of range.
+    InvalidParam(&'static str),
+}
+
+impl From<ParseIntError> for ProfileParseErr {
+    fn from(value: ParseIntError) -> Self {
+        Self::U64Parse(value)
+    }
+}
+
+impl From<ParseFloatError> for ProfileParseErr {
+    fn from(value: ParseFloatError) -> Self {
+        Self::F64Parse(value)
+    }
+}
+
+impl std::fmt::Display for ProfileParseErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownFmt(s) => write!(f, "Unknown load profile specification {}.", s),
+            Self::U64Parse(n) => write!(f, "Could not parse load profile value {} as u64.", n),
+            Self::F64Parse(n) => write!(f, "Could not parse load profile value {} as f64.", n),
+            Self::InvalidParam(e) => write!(f, "Invalid load profile parameter: {}.", e),
+        }
+    }
+}
+
+impl std::error::Error for ProfileParseErr {}
+
+/// How often [`LoadProfile::send_times`] reads the rate, so that it follows changes in the rate
+/// within this long however few requests are due.
+const RATE_STEP: Duration = Duration::from_millis(1);
+
+/// One part of a [`LoadProfile`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    /// How long the segment lasts, or `None` for the rest of the run.
+    pub len: Option<Duration>,
+    pub shape: Shape,
+}
+
+/// A sequence of [`Segment`]s giving the total open loop request rate over a run. Nothing is
+/// sent after the last segment ends.
+///
+/// Profile files hold one `[secs] [shape]` segment per line, where `shape` uses the [`Shape`]
+/// format. Blank lines and lines starting with `#` are ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadProfile(Vec<Segment>);
+
+impl LoadProfile {
+    /// A profile of one segment lasting the whole run.
+    pub fn single(shape: Shape) -> Self {
+        Self(vec![Segment { len: None, shape }])
+    }
+
+    /// Parse the contents of a profile file.
+    pub fn parse(contents: &str) -> Result<Self, ProfileParseErr> {
+        let mut segments = Vec::new();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((secs, shape)) = line.split_once(char::is_whitespace) else {
+                return Err(ProfileParseErr::UnknownFmt(line.to_owned()));
+            };
+            segments.push(Segment {
+                len: Some(parse_secs(secs)?),
+                shape: shape.trim().parse()?,
+            });
+        }
+        Ok(Self(segments))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Total rate, in req/s, at `t` into a run lasting `runtime`.
+    pub fn rate(&self, t: Duration, runtime: Duration) -> f64 {
+        let mut start = Duration::ZERO;
+        for segment in &self.0 {
+            let len = segment.len.unwrap_or(runtime.saturating_sub(start));
+            if t < start + len {
+                return segment.shape.rate(t - start, len);
+            }
+            start += len;
+        }
+        0.0
+    }
+
+    /// When, from the start of a run lasting `runtime`, each of `num_connections` connections
+    /// sends so that together they follow the profile.
+    ///
+    /// A request is sent each time the integral of the connection's share of the rate reaches
+    /// one more request, with the rate read every [`RATE_STEP`]. A low rate therefore never holds
+    /// back the sends due once the rate rises.
+    pub fn send_times(
+        self,
+        runtime: Duration,
+        num_connections: usize,
+    ) -> impl Iterator<Item = Duration> + Send {
+        let step = RATE_STEP.as_secs_f64();
+        let end = runtime.as_secs_f64();
+        // The current step, how far into it the schedule has got, and the requests owed so far.
+        let (mut k, mut t, mut owed) = (0u64, 0.0, 0.0);
+        std::iter::from_fn(move || {
+            while t < end {
+                let step_end = ((k + 1) as f64 * step).min(end);
+                let rate = self.rate(Duration::from_secs_f64(t), runtime) / num_connections as f64;
+                let due = t + (1.0 - owed) / rate;
+                if rate > 0.0 && due <= step_end {
+                    (t, owed) = (due, 0.0);
+                    let at = Duration::from_secs_f64(t);
+                    return (at < runtime).then_some(at);
+                }
+                owed += rate * (step_end - t);
+                (k, t) = (k + 1, step_end);
+            }
+            None
+        })
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::{LoadProfile, ProfileParseErr, Shape};
+    use std::time::Duration;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn parse_shapes() {
+        for s in [
+            "const:1000",
+            "ramp:1000,5000",
+            "onoff:8000,100,0.25",
+            "steps:1000/5,4000/2.5",
+            "sine:2000,1000,60",
+        ] {
+            let shape: Shape = s.parse().expect("parse shape");
+            assert_eq!(shape.to_string(), s);
+        }
+        assert!(matches!(
+            "onoff:1000,100,0".parse::<Shape>(),
+            Err(ProfileParseErr::InvalidParam(_))
+        ));
+        assert!(matches!(
+            "steps:1000".parse::<Shape>(),
+            Err(ProfileParseErr::UnknownFmt(_))
+        ));
+        assert!(matches!(
+            "steps:1000/-1".parse::<Shape>(),
+            Err(ProfileParseErr::InvalidParam(_))
+        ));
+        assert!(matches!(
+            "ramp:1000".parse::<Shape>(),
+            Err(ProfileParseErr::UnknownFmt(_))
+        ));
+        assert!(matches!(
+            "const:foo".parse::<Shape>(),
+            Err(ProfileParseErr::U64Parse(_))
+        ));
+    }
+
+    #[test]
+    fn shape_rates() {
+        let runtime = ms(10_000);
+        let ramp = LoadProfile::single("ramp:1000,2000".parse().unwrap());
+        assert_eq!(ramp.rate(ms(0), runtime), 1000.0);
+        assert_eq!(ramp.rate(ms(5000), runtime), 1500.0);
+
+        let onoff = LoadProfile::single("onoff:500,100,0.25".parse().unwrap());
+        assert_eq!(onoff.rate(ms(50), runtime), 500.0);
+        assert_eq!(onoff.rate(ms(150), runtime), 0.0);
+        assert_eq!(onoff.rate(ms(450), runtime), 500.0);
+
+        let steps = LoadProfile::single("steps:100/1,200/1".parse().unwrap());
+        assert_eq!(steps.rate(ms(500), runtime), 100.0);
+        assert_eq!(steps.rate(ms(1500), runtime), 200.0);
+        assert_eq!(steps.rate(ms(9000), runtime), 200.0);
+
+        let sine = LoadProfile::single("sine:100,200,4".parse().unwrap());
+        assert!((sine.rate(ms(1000), runtime) - 300.0).abs() < 1e-9);
+        assert_eq!(sine.rate(ms(3000), runtime), 0.0);
+    }
+
+    // Requests sent in each whole second of `runtime_secs`.
+    fn per_second(profile: LoadProfile, runtime_secs: u64, num_connections: usize) -> Vec<u64> {
+        let mut counts = vec![0; runtime_secs as usize];
+        for t in profile.send_times(Duration::from_secs(runtime_secs), num_connections) {
+            counts[t.as_secs() as usize] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn send_times_follow_rate() {
+        let steps = per_second(LoadProfile::single("steps:1/5,10000/5".parse().unwrap()), 10, 1);
+        assert!(steps[..5].iter().sum::<u64>() <= 5, "{:?}", steps);
+        for &n in &steps[5..] {
+            assert!(n.abs_diff(10_000) <= 2, "{:?}", steps);
+        }
+
+        // 1000t req/s sends 500(2k + 1) in second k, split over the connections.
+        let ramp = per_second(LoadProfile::single("ramp:0,10000".parse().unwrap()), 10, 4);
+        for (k, &n) in ramp.iter().enumerate() {
+            let expected = 500 * (2 * k as u64 + 1) / 4;
+            assert!(n.abs_diff(expected) <= expected / 100 + 2, "{:?}", ramp);
+        }
+    }
+
+    #[test]
+    fn profile_file() {
+        let profile = LoadProfile::parse("# warm up\n2 const:100\n\n1.5 ramp:100,400\n")
+            .expect("parse profile");
+        let runtime = ms(10_000);
+        assert_eq!(profile.rate(ms(1000), runtime), 100.0);
+        assert_eq!(profile.rate(ms(2750), runtime), 250.0);
+        assert_eq!(profile.rate(ms(4000), runtime), 0.0);
+        assert!(matches!(
+            LoadProfile::parse("const:100"),
+            Err(ProfileParseErr::UnknownFmt(_))
+        ));
+    }
+}
@@ -3,10 +3,40 @@ use crate::{
     chunked_tcp_stream::ChunkedTcpStream,
     serialize::{ClientWorkPacket, MessageTrait, ServerWorkPacket},
 };
-use std::{collections::HashMap, net::TcpStream};
+use std::net::TcpStream;
+
+/// Append `msg` to `out` framed the way the connections below send it: an 8-byte big-endian
+/// size header followed by the serialized message.
+pub fn encode_msg<M: MessageTrait>(msg: &M, out: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+    let mut buf = vec![0; MSG_SIZE_BYTES];
+    let sz = msg.to_bytes(&mut buf)?;
+    out.extend_from_slice(&sz.to_be_bytes());
+    out.extend_from_slice(&buf[..sz as usize]);
+    Ok(())
+}
+
+/// Decode the framed message at the front of `buf`, returning it and the number of bytes it
+/// took up, or `None` if `buf` doesn't hold a whole message yet.
+pub fn decode_msg<M: MessageTrait>(buf: &[u8]) -> Result<Option<(M, usize)>, anyhow::Error> {
+    let Some(sz_buf) = buf.get(..8) else {
+        return Ok(None);
+    };
+    let sz = u64::from_be_bytes(sz_buf.try_into().unwrap());
+    if sz > MSG_SIZE_BYTES as u64 {
+        anyhow::bail!(
+            "Message size too large: {} bytes (max: {})",
+            sz,
+            MSG_SIZE_BYTES
+        );
+    }
+    let end = 8 + sz as usize;
+    match buf.get(8..end) {
+        Some(msg) => Ok(Some((M::from_bytes(msg)?, end))),
+        None => Ok(None),
+    }
+}
 
 pub mod work_request {
-    #[allow(unused_mut)]
     use super::*;
 
     pub struct ClientWorkPacketConn {
@@ -118,7 +148,7 @@ pub mod work_response {
                 Ok(_) => eprintln!("Successfully read size header: {:?}", sz_buf),
                 Err(e) => {
                     eprintln!("Failed to read size header: {:?}", e);
-                    return Err(e.into());
+                    return Err(e);
                 }
             }
             
@@ -148,3 +178,35 @@ pub mod work_response {
         }
     }
 }
+
+#[cfg(test)]
+mod t {
+    use super::{decode_msg, encode_msg};
+    use crate::{app::Work, serialize::ClientWorkPacket};
+
+    #[test]
+    fn framing_roundtrip() {
+        let packets = [
+            ClientWorkPacket::new(1, Work::Const(5)),
+            ClientWorkPacket::new(2, Work::Immediate).with_payload(16),
+        ];
+        let mut buf = Vec::new();
+        for packet in &packets {
+            encode_msg(packet, &mut buf).expect("encode");
+        }
+
+        let (first, len) = decode_msg::<ClientWorkPacket>(&buf)
+            .expect("decode")
+            .expect("whole message");
+        assert_eq!(first, packets[0]);
+        assert!(decode_msg::<ClientWorkPacket>(&buf[len..buf.len() - 1])
+            .expect("decode partial")
+            .is_none());
+        let (second, _) = decode_msg::<ClientWorkPacket>(&buf[len..])
+            .expect("decode")
+            .expect("whole message");
+        assert_eq!(second, packets[1]);
+
+        assert!(decode_msg::<ClientWorkPacket>(&u64::MAX.to_be_bytes()).is_err());
+    }
+}
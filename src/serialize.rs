@@ -1,6 +1,10 @@
 //! Message serialization types and functions.
 
-use crate::{app::Work, get_current_time_micros};
+use crate::{
+    app::{calibrate_busy_work, Work},
+    fanout::{self, FanOutTiming},
+    get_current_time_micros,
+};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::num::NonZeroU64;
@@ -8,17 +12,26 @@ use std::time::Instant;
 
 #[derive(Debug, Clone)]
 pub struct LatencyRecord {
+    pub work: Work,
     pub latency: u64,
     pub send_timestamp: u64,
     pub server_processing_time: u64,
     pub recv_timestamp: u64,
+    /// Downstream timing, for [`Work::FanOut`] requests that met their quorum.
+    pub fanout: Option<FanOutTiming>,
+    /// Index of the server that answered, among the client's servers.
+    pub backend: usize,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// Largest request payload, in bytes, that still fits a [`ClientWorkPacket`] in one message.
+pub const MAX_REQUEST_PAYLOAD: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ClientWorkPacket {
     id: u64,
     work: Work,
     timestamp: u64,
+    payload: Vec<u8>,
 }
 
 impl ClientWorkPacket {
@@ -29,22 +42,47 @@ impl ClientWorkPacket {
             id,
             work,
             timestamp: get_current_time_micros(),
+            payload: Vec::new(),
         }
     }
 
+    /// Pad the request with `len` bytes of payload, at most [`MAX_REQUEST_PAYLOAD`].
+    pub fn with_payload(mut self, len: usize) -> Self {
+        assert!(len <= MAX_REQUEST_PAYLOAD);
+        self.payload = vec![0; len];
+        self
+    }
+
     pub fn id(&self) -> u64 {
         self.id
     }
 
     pub fn do_work(&self) -> ServerWorkPacket {
         let start = Instant::now();
-        let payload = self.work.perform();
+        let (status, payload, fanout) = match self.work {
+            // Fan-out reports its downstream timing and fails if the quorum isn't met.
+            Work::FanOut {
+                fanout,
+                quorum,
+                leaf,
+            } => match fanout::call(fanout, quorum, leaf) {
+                Ok(timing) => (ServerWorkStatus::Completed, None, Some(timing)),
+                Err(e) => {
+                    eprintln!("Fan-out work failed: {:?}", e);
+                    (ServerWorkStatus::Failed, None, None)
+                }
+            },
+            work => (ServerWorkStatus::Completed, work.perform(), None),
+        };
         let dur = start.elapsed().as_micros() as u64;
         ServerWorkPacket {
-            status: ServerWorkStatus::Completed,
+            status,
             server_processing_time: dur,
             client_id: self.id,
             client_send_time: self.timestamp,
+            work: self.work,
+            busy_work_iters_per_us: calibrate_busy_work(),
+            fanout,
             payload,
         }
     }
@@ -56,6 +94,9 @@ pub struct ServerWorkPacket {
     server_processing_time: u64,
     client_id: u64,
     client_send_time: u64,
+    work: Work,
+    busy_work_iters_per_us: f64,
+    fanout: Option<FanOutTiming>,
     payload: Option<Vec<u8>>,
 }
 
@@ -75,6 +116,11 @@ impl ServerWorkPacket {
         self.client_send_time
     }
 
+    /// The server's busy work calibration, in [`Work::BusyWorkConst`] iterations per microsecond.
+    pub fn busy_work_iters_per_us(&self) -> f64 {
+        self.busy_work_iters_per_us
+    }
+
     // Note: We calculate latency here to separate this out from student work
     pub fn calculate_latency(&self, receive_time: u64) -> Option<LatencyRecord> {
         match self.status {
@@ -90,10 +136,13 @@ impl ServerWorkPacket {
                 let actual_latency = (rtt - processing_time) / 2;
 
                 Some(LatencyRecord {
+                    work: self.work,
                     latency: actual_latency,
                     send_timestamp: self.client_send_time,
                     server_processing_time: self.server_processing_time,
                     recv_timestamp: receive_time,
+                    fanout: self.fanout,
+                    backend: 0,
                 })
             }
             ServerWorkStatus::Failed => None,
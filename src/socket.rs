@@ -0,0 +1,309 @@
+//! Socket options for the client's and the server's TCP connections, so that experiments on e.g.
+//! Nagle's algorithm and delayed ACKs control both ends the same way.
+
+use nix::sys::socket::{
+    self as nix_socket, AddressFamily, Backlog, SockFlag, SockType, SockaddrStorage,
+};
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    os::fd::{AsRawFd, OwnedFd, RawFd},
+    sync::OnceLock,
+};
+
+/// Options set on every TCP socket of a run.
+///
+/// The default only sets TCP_NODELAY. Messages are written as a header and a payload, so Nagle
+/// would hold back the payload of any message sent while the previous one is unacknowledged, e.g.
+/// the server's pipelined fan-out calls. Every client backend, the server and its fan-out calls
+/// use these options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// TCP_NODELAY: send small segments without waiting for earlier ones to be acknowledged.
+    pub nodelay: bool,
+    /// TCP_QUICKACK: acknowledge segments as soon as they arrive instead of delaying ACKs. The
+    /// kernel drops back to delayed ACKs on its own, so this is set again after every receive.
+    pub quickack: bool,
+    /// SO_RCVBUF and SO_SNDBUF in bytes, or the kernel's autotuned default if `None`. The kernel
+    /// doubles what it's given to leave room for bookkeeping.
+    pub rcvbuf: Option<u32>,
+    pub sndbuf: Option<u32>,
+    /// SO_BUSY_POLL: us that a blocking receive busy polls the device before it sleeps.
+    pub busy_poll: Option<u32>,
+    /// TCP_CORK: hold back partial segments while a message is written. Each message is uncorked
+    /// once written, so its pieces are coalesced but never held for the cork timeout.
+    pub cork: bool,
+    /// SO_REUSEPORT: let several sockets bind the same address and port.
+    pub reuseport: bool,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            quickack: false,
+            rcvbuf: None,
+            sndbuf: None,
+            busy_poll: None,
+            cork: false,
+            reuseport: false,
+        }
+    }
+}
+
+fn set(fd: RawFd, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    // Safety: every option set here takes an int.
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    match ret {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+fn get(fd: RawFd, level: libc::c_int, name: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // Safety: every option read here is an int.
+    let ret = unsafe {
+        libc::getsockopt(fd, level, name, (&mut value as *mut libc::c_int).cast(), &mut len)
+    };
+    match ret {
+        0 => Ok(value),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// The options in effect on the first socket configured by [`SocketOptions::apply`].
+static APPLIED: OnceLock<SocketOptions> = OnceLock::new();
+
+/// The options in effect on the first connection of this process, as the kernel reports them,
+/// or `None` if no connection has been configured yet.
+pub fn applied() -> Option<SocketOptions> {
+    APPLIED.get().copied()
+}
+
+impl SocketOptions {
+    /// Set the options that must be in place before `fd` connects or listens.
+    fn apply_unconnected(&self, fd: RawFd) -> io::Result<()> {
+        if self.reuseport {
+            set(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, 1)?;
+        }
+        // Buffer sizes must be set before the handshake to affect the advertised window scale.
+        if let Some(bytes) = self.rcvbuf {
+            set(fd, libc::SOL_SOCKET, libc::SO_RCVBUF, bytes as _)?;
+        }
+        if let Some(bytes) = self.sndbuf {
+            set(fd, libc::SOL_SOCKET, libc::SO_SNDBUF, bytes as _)?;
+        }
+        Ok(())
+    }
+
+    /// Set the options that apply to a connected socket, e.g. one just accepted.
+    pub fn apply(&self, stream: &impl AsRawFd) -> io::Result<()> {
+        let fd = stream.as_raw_fd();
+        set(fd, libc::IPPROTO_TCP, libc::TCP_NODELAY, self.nodelay as _)?;
+        if self.quickack {
+            set(fd, libc::IPPROTO_TCP, libc::TCP_QUICKACK, 1)?;
+        }
+        if let Some(us) = self.busy_poll {
+            set(fd, libc::SOL_SOCKET, libc::SO_BUSY_POLL, us as _)?;
+        }
+        if self.cork {
+            set(fd, libc::IPPROTO_TCP, libc::TCP_CORK, 1)?;
+        }
+        if APPLIED.get().is_none() {
+            let _ = APPLIED.set(Self::read(stream)?);
+        }
+        Ok(())
+    }
+
+    /// The options in effect on `stream`, as the kernel reports them. TCP_QUICKACK reads back
+    /// whether the connection is in quick ACK mode right now, which the kernel also enters on its
+    /// own, e.g. while a connection starts up.
+    pub fn read(stream: &impl AsRawFd) -> io::Result<Self> {
+        let fd = stream.as_raw_fd();
+        Ok(Self {
+            nodelay: get(fd, libc::IPPROTO_TCP, libc::TCP_NODELAY)? != 0,
+            quickack: get(fd, libc::IPPROTO_TCP, libc::TCP_QUICKACK)? != 0,
+            rcvbuf: Some(get(fd, libc::SOL_SOCKET, libc::SO_RCVBUF)? as u32),
+            sndbuf: Some(get(fd, libc::SOL_SOCKET, libc::SO_SNDBUF)? as u32),
+            busy_poll: Some(get(fd, libc::SOL_SOCKET, libc::SO_BUSY_POLL)? as u32),
+            cork: get(fd, libc::IPPROTO_TCP, libc::TCP_CORK)? != 0,
+            reuseport: get(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT)? != 0,
+        })
+    }
+
+    /// Call once a whole message has been written to `stream`, to push out a corked message.
+    pub fn message_sent(&self, stream: &impl AsRawFd) -> io::Result<()> {
+        if self.cork {
+            let fd = stream.as_raw_fd();
+            set(fd, libc::IPPROTO_TCP, libc::TCP_CORK, 0)?;
+            set(fd, libc::IPPROTO_TCP, libc::TCP_CORK, 1)?;
+        }
+        Ok(())
+    }
+
+    /// Call after each receive on `stream`, to keep acknowledging quickly.
+    pub fn message_received(&self, stream: &impl AsRawFd) -> io::Result<()> {
+        if self.quickack {
+            set(stream.as_raw_fd(), libc::IPPROTO_TCP, libc::TCP_QUICKACK, 1)?;
+        }
+        Ok(())
+    }
+
+    /// The options as `(name, value)` pairs, for the run manifest.
+    pub fn entries(&self) -> [(&'static str, String); 7] {
+        let bytes = |b: Option<u32>| b.map_or("default".into(), |b| b.to_string());
+        [
+            ("nodelay", self.nodelay.to_string()),
+            ("quickack", self.quickack.to_string()),
+            ("rcvbuf", bytes(self.rcvbuf)),
+            ("sndbuf", bytes(self.sndbuf)),
+            ("busy_poll_us", bytes(self.busy_poll)),
+            ("cork", self.cork.to_string()),
+            ("reuseport", self.reuseport.to_string()),
+        ]
+    }
+}
+
+/// A TCP socket of the family `family`.
+fn tcp_socket(family: AddressFamily) -> io::Result<OwnedFd> {
+    Ok(nix_socket::socket(family, SockType::Stream, SockFlag::SOCK_CLOEXEC, None)?)
+}
+
+fn family(addr: &SocketAddr) -> AddressFamily {
+    match addr {
+        SocketAddr::V4(_) => AddressFamily::Inet,
+        SocketAddr::V6(_) => AddressFamily::Inet6,
+    }
+}
+
+/// Where a client connects, and how its sockets are set up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Endpoint {
+    pub addr: SocketAddr,
+    pub options: SocketOptions,
+}
+
+impl Endpoint {
+    /// Open a connection with every option applied.
+    pub fn connect(&self) -> io::Result<TcpStream> {
+        let fd = tcp_socket(family(&self.addr))?;
+        self.options.apply_unconnected(fd.as_raw_fd())?;
+        nix_socket::connect(fd.as_raw_fd(), &SockaddrStorage::from(self.addr))?;
+        let stream = TcpStream::from(fd);
+        self.options.apply(&stream)?;
+        Ok(stream)
+    }
+}
+
+/// Listen on `addr`, with the options that accepted connections inherit already set.
+/// Connections accepted from it still need [`SocketOptions::apply`].
+///
+/// Listening on the unspecified IPv6 address `[::]` accepts IPv4 connections too.
+pub fn listen(addr: SocketAddr, options: &SocketOptions) -> io::Result<TcpListener> {
+    let fd = tcp_socket(family(&addr))?;
+    // As std's TcpListener::bind does, so that a restarted server can bind right away.
+    set(fd.as_raw_fd(), libc::SOL_SOCKET, libc::SO_REUSEADDR, 1)?;
+    if addr.ip() == Ipv6Addr::UNSPECIFIED {
+        // Whatever net.ipv6.bindv6only says.
+        set(fd.as_raw_fd(), libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, 0)?;
+    }
+    options.apply_unconnected(fd.as_raw_fd())?;
+    nix_socket::bind(fd.as_raw_fd(), &SockaddrStorage::from(addr))?;
+    nix_socket::listen(&fd, Backlog::MAXCONN)?;
+    Ok(TcpListener::from(fd))
+}
+
+/// The address for listening on every interface at `port`: dual-stack `[::]`, or `0.0.0.0` if
+/// this host has no IPv6.
+pub fn any_addr(port: u16) -> SocketAddr {
+    match tcp_socket(AddressFamily::Inet6) {
+        Ok(_) => (Ipv6Addr::UNSPECIFIED, port).into(),
+        Err(_) => (Ipv4Addr::UNSPECIFIED, port).into(),
+    }
+}
+
+/// Resolve `host`, a hostname or an IPv4 or IPv6 address, to its first address at `port`.
+pub fn resolve(host: &str, port: u16) -> io::Result<SocketAddr> {
+    // IPv6 addresses may come bracketed, as they would be with a port.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    first_addr((host, port).to_socket_addrs()?, host)
+}
+
+/// Resolve `host_port`, e.g. `server.local:8080`, `10.0.0.1:8080` or `[::1]:8080`, to its first
+/// address.
+pub fn resolve_host_port(host_port: &str) -> io::Result<SocketAddr> {
+    first_addr(host_port.to_socket_addrs()?, host_port)
+}
+
+fn first_addr(mut addrs: impl Iterator<Item = SocketAddr>, name: &str) -> io::Result<SocketAddr> {
+    addrs.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} has no addresses", name))
+    })
+}
+
+#[cfg(test)]
+mod t {
+    use super::{listen, resolve, resolve_host_port, Endpoint, SocketOptions};
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    #[test]
+    fn options_applied() {
+        let options = SocketOptions {
+            nodelay: false,
+            rcvbuf: Some(64 * 1024),
+            cork: true,
+            reuseport: true,
+            ..Default::default()
+        };
+        let listener = listen((Ipv4Addr::LOCALHOST, 0).into(), &options).expect("listen");
+        let addr = listener.local_addr().unwrap();
+        // Another listener can share the port.
+        listen(addr, &options).expect("listen with SO_REUSEPORT");
+
+        let stream = Endpoint { addr, options }.connect().expect("connect");
+        let applied = SocketOptions::read(&stream).expect("read options");
+        assert!(!applied.nodelay);
+        assert!(applied.cork);
+        assert!(applied.reuseport);
+        // The kernel doubles the requested size.
+        assert_eq!(applied.rcvbuf, Some(128 * 1024));
+        options.message_sent(&stream).expect("uncork");
+        assert!(SocketOptions::read(&stream).expect("read options").cork);
+    }
+
+    #[test]
+    fn dual_stack() {
+        let options = SocketOptions::default();
+        let listener = listen((Ipv6Addr::UNSPECIFIED, 0).into(), &options).expect("listen on [::]");
+        let port = listener.local_addr().unwrap().port();
+        for addr in [
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+        ] {
+            Endpoint { addr, options }.connect().expect("connect");
+        }
+    }
+
+    #[test]
+    fn resolve_names() {
+        assert_eq!(resolve("::1", 80).unwrap(), SocketAddr::from((Ipv6Addr::LOCALHOST, 80)));
+        assert_eq!(resolve("[::1]", 80).unwrap(), SocketAddr::from((Ipv6Addr::LOCALHOST, 80)));
+        assert_eq!(resolve("127.0.0.1", 80).unwrap(), SocketAddr::from((Ipv4Addr::LOCALHOST, 80)));
+        assert!(resolve("localhost", 80).unwrap().ip().is_loopback());
+        assert_eq!(
+            resolve_host_port("[::1]:8080").unwrap(),
+            SocketAddr::from((Ipv6Addr::LOCALHOST, 8080))
+        );
+        assert!(resolve_host_port("localhost").is_err());
+    }
+}
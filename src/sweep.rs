@@ -0,0 +1,275 @@
+//! Load sweeps that step a client through a series of load points to build a throughput-latency
+//! curve in one invocation.
+
+use crate::{
+    app::WorkMix,
+    balance::Servers,
+    closed_loop_client::{self, UserModel},
+    engine::Clients,
+    metrics::{MeasurementConfig, RunSummary},
+    open_loop_client,
+};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    num::ParseIntError,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// A list of load points.
+///
+/// Implements [`FromStr`](std::str::FromStr). String format is either a comma-separated list
+/// `a,b,c` or an inclusive range `start:end:step`. All values must be nonzero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Steps(pub Vec<u64>);
+
+impl std::str::FromStr for Steps {
+    type Err = StepsParseErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sp: Vec<_> = s.split(':').collect();
+        let steps = match &sp[..] {
+            [list] => list
+                .split(',')
+                .map(|x| x.trim().parse())
+                .collect::<Result<Vec<u64>, _>>()?,
+            [start, end, step] => {
+                let (start, end, step): (u64, u64, u64) =
+                    (start.parse()?, end.parse()?, step.parse()?);
+                if step == 0 || start > end {
+                    return Err(StepsParseErr::UnknownFmt(s.to_owned()));
+                }
+                (start..=end).step_by(step as usize).collect()
+            }
+            _ => return Err(StepsParseErr::UnknownFmt(s.to_owned())),
+        };
+        if steps.contains(&0) {
+            return Err(StepsParseErr::ZeroValue);
+        }
+        Ok(Steps(steps))
+    }
+}
+
+/// Things that can go wrong when parsing [`Steps`].
+#[derive(Debug)]
+pub enum StepsParseErr {
+    /// Neither the `a,b,c` nor the `start:end:step` format was followed.
+    UnknownFmt(String),
+    /// One of the load points was zero.
+    ZeroValue,
+    /// A load point wasn't a `u64`.
+    U64Parse(ParseIntError),
+}
+
+impl From<ParseIntError> for StepsParseErr {
+    fn from(value: ParseIntError) -> Self {
+        Self::U64Parse(value)
+    }
+}
+
+impl std::fmt::Display for StepsParseErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownFmt(s) => {
+                write!(f, "Unknown load step specification {}. Format is a,b,c or start:end:step.", s)
+            }
+            Self::ZeroValue => write!(f, "Load steps must be nonzero."),
+            Self::U64Parse(n) => write!(f, "Could not parse load step {} as u64.", n),
+        }
+    }
+}
+
+impl std::error::Error for StepsParseErr {}
+
+/// What a sweep varies from one point to the next.
+#[derive(Debug, Clone)]
+pub enum SweepKind {
+    /// Open loop total request rates in req/s, spread over a fixed set of connections.
+    Rates { rates: Vec<u64>, clients: Clients },
+    /// Closed loop thread counts for `clients`, each user behaving as `users`. With the threads
+    /// backend every thread is a user; other backends keep the same users on more threads.
+    Threads {
+        threads: Vec<usize>,
+        clients: Clients,
+        users: UserModel,
+    },
+}
+
+/// The result of one point of a sweep.
+#[derive(Debug, Clone, Copy)]
+pub struct SweepPoint {
+    /// The offered rate (req/s) or thread count for this point.
+    pub point: u64,
+    pub summary: RunSummary,
+}
+
+/// Unmeasured time around each point of a sweep.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pacing {
+    /// Discarded run before each point, at the same load.
+    pub warmup: Duration,
+    /// Pause after each point so that the server can drain any queued requests before the next
+    /// point starts.
+    pub settle: Duration,
+}
+
+/// Run every point of `kind` for `runtime`, and write a summary table to `outdir/sweep.csv`.
+///
+/// Each point is measured according to `measurement`, and preceded and followed by the unmeasured
+/// runs and pauses of `pacing`. Raw records and time series for each point are written to
+/// `outdir/<point>/`.
+pub fn run(
+    servers: &Servers,
+    kind: SweepKind,
+    runtime: Duration,
+    measurement: MeasurementConfig,
+    pacing: Pacing,
+    work: WorkMix,
+    outdir: PathBuf,
+) -> Result<Vec<SweepPoint>, anyhow::Error> {
+    fs::create_dir_all(&outdir)?;
+
+    let (points, label) = match &kind {
+        SweepKind::Rates { rates, .. } => (rates.clone(), "offered_rps"),
+        SweepKind::Threads { threads, .. } => (threads.iter().map(|&t| t as u64).collect(), "threads"),
+    };
+
+    let mut results = Vec::new();
+    for point in points {
+        let point_dir = outdir.join(point.to_string());
+        let run_point = |runtime| match kind {
+            SweepKind::Rates { clients, .. } => open_loop_client::run(
+                servers,
+                clients,
+                open_loop_client::interarrival_for_rate(point, clients.connections),
+                runtime,
+                work.clone(),
+                measurement,
+                point_dir.clone(),
+            ),
+            SweepKind::Threads { clients, users, .. } => closed_loop_client::run(
+                servers,
+                clients.with_threads(point as usize),
+                runtime,
+                work.clone(),
+                users,
+                measurement,
+                point_dir.clone(),
+            ),
+        };
+
+        println!("\n=== Sweep point {} = {} ===", label, point);
+        if !pacing.warmup.is_zero() {
+            run_point(pacing.warmup);
+        }
+        let summary = run_point(runtime);
+        results.push(SweepPoint { point, summary });
+        thread::sleep(pacing.settle);
+    }
+
+    print_table(label, &results);
+    if let Some(limit) = results.iter().find(|p| p.summary.generator.bottleneck) {
+        println!(
+            "\nThe client could not send any faster from {} = {} on (achieved {:.2} req/s). \
+             Points from there measure the client, not the server.",
+            label, limit.point, limit.summary.achieved_load
+        );
+    }
+    write_csv(&outdir.join("sweep.csv"), label, &results)?;
+    Ok(results)
+}
+
+fn print_table(label: &str, results: &[SweepPoint]) {
+    println!("\nSweep Summary:");
+    println!(
+        "{:>12} {:>14} {:>14} {:>10} {:>10} {:>10} {:>8} {:>10} {:>8}",
+        label,
+        "attempted_rps",
+        "achieved_rps",
+        "p50_us",
+        "p95_us",
+        "p99_us",
+        "errors",
+        "client_cpu",
+        "client"
+    );
+    for SweepPoint { point, summary } in results {
+        let (p50, p95, p99) = match summary.latency {
+            Some(l) => (format!("{:.1}", l.p50), format!("{:.1}", l.p95), format!("{:.1}", l.p99)),
+            None => ("-".into(), "-".into(), "-".into()),
+        };
+        println!(
+            "{:>12} {:>14.2} {:>14.2} {:>10} {:>10} {:>10} {:>8} {:>9.1}% {:>8}",
+            point,
+            summary.attempted_load,
+            summary.achieved_load,
+            p50,
+            p95,
+            p99,
+            summary.errors,
+            100.0 * summary.generator.process_cpu,
+            if summary.generator.bottleneck { "limited" } else { "ok" }
+        );
+    }
+}
+
+fn write_csv(path: &Path, label: &str, results: &[SweepPoint]) -> Result<(), anyhow::Error> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(
+        out,
+        "{},attempted_rps,achieved_rps,p50_us,p95_us,p99_us,errors,client_cpu,slip_p99_us,late_fraction,client_limited",
+        label
+    )?;
+    for SweepPoint { point, summary } in results {
+        let latency = match summary.latency {
+            Some(l) => format!("{:.1},{:.1},{:.1}", l.p50, l.p95, l.p99),
+            None => ",,".into(),
+        };
+        let client = &summary.generator;
+        let slip = client.slip.map_or(String::new(), |s| format!("{:.0}", s.p99));
+        writeln!(
+            out,
+            "{},{:.2},{:.2},{},{},{:.3},{},{:.4},{}",
+            point,
+            summary.attempted_load,
+            summary.achieved_load,
+            latency,
+            summary.errors,
+            client.process_cpu,
+            slip,
+            client.late_fraction,
+            client.bottleneck
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::{Steps, StepsParseErr};
+
+    #[test]
+    fn parse_steps_list() {
+        assert_eq!("1,2,4,8".parse::<Steps>().expect("parse list"), Steps(vec![1, 2, 4, 8]));
+        assert_eq!("1000".parse::<Steps>().expect("parse single"), Steps(vec![1000]));
+        assert!(matches!("1,foo".parse::<Steps>(), Err(StepsParseErr::U64Parse(_))));
+        assert!(matches!("0,1".parse::<Steps>(), Err(StepsParseErr::ZeroValue)));
+    }
+
+    #[test]
+    fn parse_steps_range() {
+        assert_eq!(
+            "1000:4000:1000".parse::<Steps>().expect("parse range"),
+            Steps(vec![1000, 2000, 3000, 4000])
+        );
+        assert_eq!(
+            "1000:3500:1000".parse::<Steps>().expect("parse range"),
+            Steps(vec![1000, 2000, 3000])
+        );
+        assert!(matches!("1:10:0".parse::<Steps>(), Err(StepsParseErr::UnknownFmt(_))));
+        assert!(matches!("10:1:1".parse::<Steps>(), Err(StepsParseErr::UnknownFmt(_))));
+        assert!(matches!("1:10".parse::<Steps>(), Err(StepsParseErr::UnknownFmt(_))));
+    }
+}
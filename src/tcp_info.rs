@@ -0,0 +1,278 @@
+//! Kernel TCP state sampled from connections with `getsockopt(TCP_INFO)`, to tell latency added by
+//! the network stack apart from latency added by queueing in the server.
+
+use crate::{get_current_time_micros, metrics::Percentiles};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    os::fd::{AsRawFd, RawFd},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// One connection's TCP state at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpInfoSample {
+    /// The connection the sample was taken from, numbered within the run.
+    pub conn: usize,
+    pub timestamp_us: u64,
+    /// Smoothed round trip time and its mean deviation, in us.
+    pub srtt_us: u32,
+    pub rttvar_us: u32,
+    /// Segments retransmitted over the connection's lifetime.
+    pub total_retrans: u32,
+    /// Congestion window, in segments.
+    pub cwnd: u32,
+    /// Segments sent but not yet acknowledged.
+    pub unacked: u32,
+    /// Bytes sent but not yet acknowledged.
+    pub bytes_in_flight: u64,
+}
+
+/// The count an ioctl that reports a byte count writes, or `None` if it fails.
+fn ioctl_count(fd: RawFd, request: libc::c_ulong) -> Option<u64> {
+    let mut n: libc::c_int = 0;
+    // Safety: the requests used here write one int through the pointer.
+    match unsafe { libc::ioctl(fd, request as _, &mut n) } {
+        0 => Some(n.max(0) as u64),
+        _ => None,
+    }
+}
+
+/// Take a sample of `stream`'s TCP state, labelled as connection `conn`.
+pub fn sample(conn: usize, stream: &impl AsRawFd) -> io::Result<TcpInfoSample> {
+    // Safety: tcp_info is plain old data, and the kernel writes at most `len` bytes of it.
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            (&mut info as *mut libc::tcp_info).cast(),
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Everything in the send queue has been sent, except for what SIOCOUTQNSD reports.
+    let fd = stream.as_raw_fd();
+    let in_flight = ioctl_count(fd, libc::TIOCOUTQ as _)
+        .zip(ioctl_count(fd, libc::SIOCOUTQNSD as _))
+        .map_or(0, |(queued, unsent)| queued.saturating_sub(unsent));
+    Ok(TcpInfoSample {
+        conn,
+        timestamp_us: get_current_time_micros(),
+        srtt_us: info.tcpi_rtt,
+        rttvar_us: info.tcpi_rttvar,
+        total_retrans: info.tcpi_total_retrans,
+        cwnd: info.tcpi_snd_cwnd,
+        unacked: info.tcpi_unacked,
+        bytes_in_flight: in_flight,
+    })
+}
+
+/// Samples the TCP state of one thread's connections every `interval`.
+///
+/// Owned by the thread that uses the connections, so that a socket is never sampled while it is
+/// being closed. Sampling happens when the thread checks in, so idle threads sample less often.
+pub struct TcpInfoSampler {
+    interval: Option<Duration>,
+    next: Instant,
+    samples: Vec<TcpInfoSample>,
+}
+
+impl TcpInfoSampler {
+    /// A sampler that samples every `interval`, or never if `None`.
+    pub fn new(interval: Option<Duration>) -> Self {
+        Self {
+            interval,
+            next: Instant::now(),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Whether the next round of samples is due. If so, the round after is scheduled.
+    pub fn due(&mut self) -> bool {
+        let Some(interval) = self.interval else {
+            return false;
+        };
+        let now = Instant::now();
+        if now < self.next {
+            return false;
+        }
+        self.next = now + interval;
+        true
+    }
+
+    /// Sample `stream` now, as connection `conn`. Failures, e.g. on a socket that has since
+    /// closed, are ignored.
+    pub fn record(&mut self, conn: usize, stream: &impl AsRawFd) {
+        if let Ok(s) = sample(conn, stream) {
+            self.samples.push(s);
+        }
+    }
+
+    /// Sample `stream`, as connection `conn`, if a round is due.
+    pub fn sample_if_due(&mut self, conn: usize, stream: &impl AsRawFd) {
+        if self.due() {
+            self.record(conn, stream);
+        }
+    }
+
+    /// The samples taken so far, leaving none behind.
+    pub fn take(&mut self) -> Vec<TcpInfoSample> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+/// Samples collected from many threads, e.g. each of the server's connection handlers.
+#[derive(Debug, Clone, Default)]
+pub struct SampleSink(Arc<Mutex<Vec<TcpInfoSample>>>);
+
+impl SampleSink {
+    pub fn extend(&self, samples: Vec<TcpInfoSample>) {
+        if !samples.is_empty() {
+            self.0.lock().unwrap().extend(samples);
+        }
+    }
+
+    pub fn take(&self) -> Vec<TcpInfoSample> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+/// Summary of a run's TCP state samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TcpInfoSummary {
+    pub samples: usize,
+    pub connections: usize,
+    /// Percentiles of the smoothed RTT, in us.
+    pub srtt: Percentiles,
+    pub max_srtt_us: u32,
+    /// Retransmitted segments, summed over connections.
+    pub retransmits: u64,
+    /// Smallest and median congestion window, in segments.
+    pub min_cwnd: u32,
+    pub median_cwnd: u32,
+    pub max_unacked: u32,
+    pub max_bytes_in_flight: u64,
+}
+
+impl TcpInfoSummary {
+    /// Summarize `samples`, or `None` if there are none.
+    pub fn new(samples: &[TcpInfoSample]) -> Option<Self> {
+        let mut srtt: Vec<u64> = samples.iter().map(|s| s.srtt_us as u64).collect();
+        let srtt = Percentiles::from_unsorted(&mut srtt)?;
+        let mut cwnd: Vec<u32> = samples.iter().map(|s| s.cwnd).collect();
+        cwnd.sort_unstable();
+
+        // Retransmit counts are cumulative, so each connection's last count is its total.
+        let mut retrans = BTreeMap::new();
+        for s in samples {
+            let r = retrans.entry(s.conn).or_insert(0);
+            *r = s.total_retrans.max(*r);
+        }
+
+        Some(Self {
+            samples: samples.len(),
+            connections: retrans.len(),
+            srtt,
+            max_srtt_us: samples.iter().map(|s| s.srtt_us).max().unwrap_or(0),
+            retransmits: retrans.values().map(|&r| r as u64).sum(),
+            min_cwnd: cwnd[0],
+            median_cwnd: cwnd[cwnd.len() / 2],
+            max_unacked: samples.iter().map(|s| s.unacked).max().unwrap_or(0),
+            max_bytes_in_flight: samples.iter().map(|s| s.bytes_in_flight).max().unwrap_or(0),
+        })
+    }
+}
+
+/// Print a summary of `samples` and write them to `outdir/<name>.csv`. Does nothing if there are
+/// none.
+pub fn report(outdir: &Path, name: &str, samples: &[TcpInfoSample]) -> Result<(), anyhow::Error> {
+    let Some(summary) = TcpInfoSummary::new(samples) else {
+        return Ok(());
+    };
+    println!(
+        "\nTCP Info ({} samples over {} connections):",
+        summary.samples, summary.connections
+    );
+    println!(
+        "Smoothed RTT: p50 {:.0} us, p99 {:.0} us, max {} us",
+        summary.srtt.p50, summary.srtt.p99, summary.max_srtt_us
+    );
+    println!("Retransmitted segments: {}", summary.retransmits);
+    println!(
+        "Congestion window: min {} segments, median {} segments",
+        summary.min_cwnd, summary.median_cwnd
+    );
+    println!(
+        "In flight: max {} unacked segments, max {} bytes",
+        summary.max_unacked, summary.max_bytes_in_flight
+    );
+
+    std::fs::create_dir_all(outdir)?;
+    let mut out = BufWriter::new(File::create(outdir.join(format!("{}.csv", name)))?);
+    writeln!(
+        out,
+        "conn,timestamp_us,srtt_us,rttvar_us,total_retrans,cwnd,unacked,bytes_in_flight"
+    )?;
+    for s in samples {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{}",
+            s.conn,
+            s.timestamp_us,
+            s.srtt_us,
+            s.rttvar_us,
+            s.total_retrans,
+            s.cwnd,
+            s.unacked,
+            s.bytes_in_flight
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::{sample, TcpInfoSample, TcpInfoSummary};
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn sample_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).expect("connect");
+        let s = sample(3, &stream).expect("sample TCP_INFO");
+        assert_eq!(s.conn, 3);
+        assert!(s.cwnd > 0);
+        assert!(sample(0, &listener).is_ok());
+    }
+
+    #[test]
+    fn summarize_samples() {
+        let s = |conn, srtt_us, total_retrans, cwnd| TcpInfoSample {
+            conn,
+            timestamp_us: 0,
+            srtt_us,
+            rttvar_us: 0,
+            total_retrans,
+            cwnd,
+            unacked: 0,
+            bytes_in_flight: 0,
+        };
+        let summary = TcpInfoSummary::new(&[s(0, 100, 1, 10), s(0, 300, 2, 12), s(1, 200, 5, 8)])
+            .expect("nonempty");
+        assert_eq!(summary.connections, 2);
+        assert_eq!(summary.retransmits, 7);
+        assert_eq!(summary.srtt.p50, 200.0);
+        assert_eq!(summary.max_srtt_us, 300);
+        assert_eq!((summary.min_cwnd, summary.median_cwnd), (8, 10));
+        assert!(TcpInfoSummary::new(&[]).is_none());
+    }
+}
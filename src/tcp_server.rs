@@ -1,11 +1,13 @@
 use crate::{
+    affinity,
     protocol::{work_request::ClientWorkPacketConn, work_response::ServerWorkPacketConn},
-    serialize::ClientWorkPacket,
+    socket::{self, SocketOptions},
+    tcp_info::{SampleSink, TcpInfoSampler},
 };
 
 use std::{
-    net::{SocketAddrV4, TcpListener, TcpStream},
-    sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, atomic::{AtomicUsize, Ordering}},
     thread,
     time::{Duration, Instant},
 };
@@ -15,19 +17,33 @@ struct ServerLoadTracker {
     received_requests: AtomicUsize,
     completed_requests: AtomicUsize,
     start_time: Instant,
+    // Connections accepted and requests received by each listener
+    listener_connections: Vec<AtomicUsize>,
+    listener_requests: Vec<AtomicUsize>,
+    accepted: AtomicUsize,
 }
 
 impl ServerLoadTracker {
-    fn new() -> Self {
+    fn new(listeners: usize) -> Self {
         ServerLoadTracker {
             received_requests: AtomicUsize::new(0),
             completed_requests: AtomicUsize::new(0),
             start_time: Instant::now(),
+            listener_connections: (0..listeners).map(|_| AtomicUsize::new(0)).collect(),
+            listener_requests: (0..listeners).map(|_| AtomicUsize::new(0)).collect(),
+            accepted: AtomicUsize::new(0),
         }
     }
 
-    fn record_received(&self) {
+    // Returns the new connection's id, unique across listeners
+    fn record_accepted(&self, listener: usize) -> usize {
+        self.listener_connections[listener].fetch_add(1, Ordering::SeqCst);
+        self.accepted.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn record_received(&self, listener: usize) {
         self.received_requests.fetch_add(1, Ordering::SeqCst);
+        self.listener_requests[listener].fetch_add(1, Ordering::SeqCst);
     }
 
     fn record_completed(&self) {
@@ -69,29 +85,121 @@ impl ServerLoadTracker {
         if received > 0 {
             println!("Completion rate: {:.2}%", (completed as f64 / received as f64) * 100.0);
         }
+        if self.listener_connections.len() > 1 {
+            for (i, (conns, requests)) in self
+                .listener_connections
+                .iter()
+                .zip(&self.listener_requests)
+                .enumerate()
+            {
+                println!(
+                    "Listener {}: {} connections, {} requests",
+                    i,
+                    conns.load(Ordering::SeqCst),
+                    requests.load(Ordering::SeqCst)
+                );
+            }
+        }
     }
 }
 
-pub fn tcp_server(addr: SocketAddrV4) -> Result<(), anyhow::Error> {
-    let listener = TcpListener::bind(addr).unwrap();
-    let load_tracker = Arc::new(ServerLoadTracker::new());
-    
+/// How the server treats its connections.
+#[derive(Debug, Clone, Default)]
+pub struct ServerOptions {
+    pub sockets: SocketOptions,
+    /// If set, sample each connection's kernel TCP state at this interval into `tcp_info`.
+    pub tcp_info_interval: Option<Duration>,
+    pub tcp_info: SampleSink,
+}
+
+fn start_metrics(load_tracker: &Arc<ServerLoadTracker>) {
     // Periodically print metrics
-    let tracker_clone = Arc::clone(&load_tracker);
+    let tracker_clone = Arc::clone(load_tracker);
     thread::spawn(move || {
         loop {
             thread::sleep(Duration::from_secs(20));
             tracker_clone.print_metrics();
         }
     });
-    
-    
+}
+
+/// Accept every connection on one listener and serve each on a thread of its own, placed on a
+/// CPU according to the server's pinning.
+pub fn tcp_server(addr: SocketAddr, options: ServerOptions) -> Result<(), anyhow::Error> {
+    let listener = socket::listen(addr, &options.sockets)?;
+    let load_tracker = Arc::new(ServerLoadTracker::new(1));
+    start_metrics(&load_tracker);
+    accept_loop(listener, 0, load_tracker, options, true);
+    Ok(())
+}
+
+/// Open `listeners` listeners on `addr` with SO_REUSEPORT, so that the kernel spreads incoming
+/// connections over them, and accept on each from a thread pinned to a CPU of its own.
+///
+/// Listener `i` takes the `i`th of the server's CPUs, round robin, and its connections' threads
+/// stay on that CPU, unlike [`tcp_server`]'s which are dispatched from one accept loop.
+pub fn reuseport_server(
+    addr: SocketAddr,
+    listeners: usize,
+    options: ServerOptions,
+) -> Result<(), anyhow::Error> {
+    let options = ServerOptions {
+        sockets: SocketOptions {
+            reuseport: true,
+            ..options.sockets
+        },
+        ..options
+    };
+    // Every listener joins the port's group before any accepts, so the kernel spreads connections
+    // over all of them from the start.
+    let sockets: Vec<_> = (0..listeners)
+        .map(|_| socket::listen(addr, &options.sockets))
+        .collect::<Result<_, _>>()?;
+    let load_tracker = Arc::new(ServerLoadTracker::new(listeners));
+    start_metrics(&load_tracker);
+
+    let handles: Vec<_> = sockets
+        .into_iter()
+        .enumerate()
+        .map(|(i, listener)| {
+            let load_tracker = Arc::clone(&load_tracker);
+            let options = options.clone();
+            thread::spawn(move || {
+                match affinity::pin_thread_to(i) {
+                    Ok(cpu) => println!("Listener {} pinned to CPU {}", i, cpu),
+                    Err(e) => eprintln!("Failed to pin listener {}: {}", i, e),
+                }
+                accept_loop(listener, i, load_tracker, options, false);
+            })
+        })
+        .collect();
+    for handle in handles {
+        let _ = handle.join();
+    }
+    Ok(())
+}
+
+// Serve each connection accepted on `listener`, number `index` of the server's listeners, on its
+// own thread. The threads are placed on CPUs if `place` is set, and otherwise inherit the
+// accepting thread's CPUs.
+fn accept_loop(
+    listener: TcpListener,
+    index: usize,
+    load_tracker: Arc<ServerLoadTracker>,
+    options: ServerOptions,
+    place: bool,
+) {
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
+                let id = load_tracker.record_accepted(index);
                 let tracker_clone = Arc::clone(&load_tracker);
+                let options = options.clone();
                 thread::spawn(move || {
-                    if let Err(e) = handle_conn(stream, tracker_clone) {
+                    if place {
+                        affinity::place_thread();
+                    }
+                    if let Err(e) = handle_conn(stream, id, index, tracker_clone, options) {
                         eprintln!("Connection handler error: {:?}", e);
                     }
                 });
@@ -101,13 +209,23 @@ pub fn tcp_server(addr: SocketAddrV4) -> Result<(), anyhow::Error> {
             }
         }
     }
-    Ok(())
 }
 
-fn handle_conn(stream: TcpStream, load_tracker: Arc<ServerLoadTracker>) -> Result<(), anyhow::Error> {    
+fn handle_conn(
+    stream: TcpStream,
+    id: usize,
+    listener: usize,
+    load_tracker: Arc<ServerLoadTracker>,
+    options: ServerOptions,
+) -> Result<(), anyhow::Error> {
+    options.sockets.apply(&stream)?;
     let mut client_conn = ClientWorkPacketConn::new(&stream);
     let mut server_conn = ServerWorkPacketConn::new(&stream);
+    let mut tcp_info = TcpInfoSampler::new(options.tcp_info_interval);
     loop {
+        // Handed over as they're taken, since the server may exit with the connection open.
+        tcp_info.sample_if_due(id, &stream);
+        options.tcp_info.extend(tcp_info.take());
         let work_packet = match client_conn.recv_work_msg() {
             Ok(packet) => packet,
             Err(e) => {
@@ -115,12 +233,14 @@ fn handle_conn(stream: TcpStream, load_tracker: Arc<ServerLoadTracker>) -> Resul
                 break; // Exit the loop if an error occurs (e.g., connection closed)
             }
         };
-        load_tracker.record_received();
+        options.sockets.message_received(&stream)?;
+        load_tracker.record_received(listener);
         let server_work_packet = work_packet.do_work();
         if let Err(e) = server_conn.send_work_msg(server_work_packet) {
             eprintln!("Error sending work packet: {:?}", e);
             break; // Exit the loop if sending fails
         }
+        options.sockets.message_sent(&stream)?;
         load_tracker.record_completed();   
     }
     Ok(())
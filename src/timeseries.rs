@@ -0,0 +1,280 @@
+//! Per-interval metrics sampled while a load generator runs.
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Latency buckets below this many us each hold a single value.
+const EXACT_BUCKETS: usize = 2 << SUB_BUCKET_BITS;
+/// Each power of two above [`EXACT_BUCKETS`] us is split into `1 << SUB_BUCKET_BITS` buckets, so
+/// that a bucket's latencies are within about 3% of each other.
+const SUB_BUCKET_BITS: u32 = 5;
+/// Enough buckets for any `u64` latency.
+const NUM_BUCKETS: usize = (65 - SUB_BUCKET_BITS as usize) << SUB_BUCKET_BITS;
+
+/// The histogram bucket `latency` falls in.
+fn bucket(latency: u64) -> usize {
+    if latency < EXACT_BUCKETS as u64 {
+        return latency as usize;
+    }
+    let shift = 63 - latency.leading_zeros() - SUB_BUCKET_BITS;
+    ((shift as usize) << SUB_BUCKET_BITS) + (latency >> shift) as usize
+}
+
+/// The lowest latency in bucket `i`.
+fn bucket_floor(i: usize) -> u64 {
+    if i < EXACT_BUCKETS {
+        return i as u64;
+    }
+    let shift = (i >> SUB_BUCKET_BITS) - 1;
+    ((i as u64 & ((1 << SUB_BUCKET_BITS) - 1)) | 1 << SUB_BUCKET_BITS) << shift
+}
+
+/// Counters shared by a run's client threads and its sampler thread.
+///
+/// A disabled recorder ignores every update, so client threads can record unconditionally.
+pub struct IntervalRecorder {
+    enabled: bool,
+    sent: AtomicU64,
+    received: AtomicU64,
+    errors: AtomicU64,
+    /// Responses received this interval by latency, bucketed so that recording one is a single
+    /// atomic add rather than contending on a lock.
+    latencies: Box<[AtomicU64]>,
+    stopped: AtomicBool,
+}
+
+impl IntervalRecorder {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            latencies: (0..NUM_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    pub fn record_sent(&self) {
+        if self.enabled {
+            self.sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_received(&self, latency: u64) {
+        if self.enabled {
+            self.received.fetch_add(1, Ordering::Relaxed);
+            self.latencies[bucket(latency)].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_error(&self) {
+        if self.enabled {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Tell the sampler thread to take a final sample and exit.
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    fn take_sample(&self, elapsed: Duration) -> IntervalSample {
+        let counts: Vec<u64> = self
+            .latencies
+            .iter()
+            .map(|n| n.swap(0, Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        // As metrics::percentile does, but with the lowest latency of the bucket the rank falls in.
+        let percentile = |q: f64| {
+            let rank = ((total as f64 * q) as u64).min(total - 1);
+            let mut seen = 0;
+            let i = counts
+                .iter()
+                .position(|&n| {
+                    seen += n;
+                    seen > rank
+                })
+                .expect("rank is below the total");
+            bucket_floor(i)
+        };
+        let (p50, p99) = if total == 0 {
+            (None, None)
+        } else {
+            (Some(percentile(0.5)), Some(percentile(0.99)))
+        };
+        IntervalSample {
+            elapsed,
+            sent: self.sent.swap(0, Ordering::Relaxed),
+            received: self.received.swap(0, Ordering::Relaxed),
+            errors: self.errors.swap(0, Ordering::Relaxed),
+            p50,
+            p99,
+        }
+    }
+}
+
+/// What happened during one sampling interval.
+#[derive(Debug, Clone, Copy)]
+pub struct IntervalSample {
+    /// Time since the start of the run at the end of this interval.
+    pub elapsed: Duration,
+    pub sent: u64,
+    pub received: u64,
+    pub errors: u64,
+    /// Median latency, in us, of the responses received during this interval, rounded down by
+    /// at most about 3%.
+    pub p50: Option<u64>,
+    /// 99th percentile latency, in us, of the responses received during this interval, rounded
+    /// down by at most about 3%.
+    pub p99: Option<u64>,
+}
+
+impl std::fmt::Display for IntervalSample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fmt_us = |x: Option<u64>| x.map_or("-".to_owned(), |x| format!("{}us", x));
+        write!(
+            f,
+            "[{:>8.2}s] sent {:>8} recv {:>8} err {:>4} p50 {:>9} p99 {:>9}",
+            self.elapsed.as_secs_f64(),
+            self.sent,
+            self.received,
+            self.errors,
+            fmt_us(self.p50),
+            fmt_us(self.p99)
+        )
+    }
+}
+
+/// A run's [`IntervalRecorder`] and, if sampling is enabled, the thread sampling it.
+pub struct Sampler {
+    recorder: Arc<IntervalRecorder>,
+    handle: Option<JoinHandle<Vec<IntervalSample>>>,
+}
+
+impl Sampler {
+    /// Start sampling every `interval`, printing each sample if `live`. If `interval` is `None`,
+    /// the recorder is disabled and no thread is spawned.
+    pub fn start(interval: Option<Duration>, live: bool) -> Self {
+        let recorder = Arc::new(IntervalRecorder::new(interval.is_some()));
+        let handle = interval.map(|interval| spawn_sampler(recorder.clone(), interval, live));
+        Self { recorder, handle }
+    }
+
+    pub fn recorder(&self) -> Arc<IntervalRecorder> {
+        self.recorder.clone()
+    }
+
+    /// Stop sampling and write the samples, if any, to `outdir/timeseries.csv`.
+    pub fn finish(self, outdir: &Path) -> Result<(), anyhow::Error> {
+        self.recorder.stop();
+        match self.handle {
+            Some(handle) => {
+                let samples = handle.join().expect("sampler thread panicked");
+                write_samples(outdir, &samples)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Spawn a thread that samples `recorder` every `interval` until the recorder is stopped,
+/// optionally printing each sample as it is taken.
+fn spawn_sampler(
+    recorder: Arc<IntervalRecorder>,
+    interval: Duration,
+    live: bool,
+) -> JoinHandle<Vec<IntervalSample>> {
+    thread::spawn(move || {
+        let start = Instant::now();
+        let mut next_sample = start + interval;
+        let mut samples = Vec::new();
+        while !recorder.stopped.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            if now < next_sample {
+                thread::sleep((next_sample - now).min(Duration::from_millis(10)));
+                continue;
+            }
+            next_sample += interval;
+            let sample = recorder.take_sample(start.elapsed());
+            if live {
+                println!("{}", sample);
+            }
+            samples.push(sample);
+        }
+        samples.push(recorder.take_sample(start.elapsed()));
+        samples
+    })
+}
+
+/// Write samples to `outdir/timeseries.csv`.
+pub fn write_samples(outdir: &Path, samples: &[IntervalSample]) -> Result<(), anyhow::Error> {
+    fs::create_dir_all(outdir)?;
+    let mut out = BufWriter::new(File::create(outdir.join("timeseries.csv"))?);
+    writeln!(out, "elapsed_ms,sent,received,errors,p50_us,p99_us")?;
+    for s in samples {
+        let fmt_us = |x: Option<u64>| x.map_or(String::new(), |x| x.to_string());
+        writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            s.elapsed.as_millis(),
+            s.sent,
+            s.received,
+            s.errors,
+            fmt_us(s.p50),
+            fmt_us(s.p99)
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::{bucket, bucket_floor, IntervalRecorder, NUM_BUCKETS};
+    use std::time::Duration;
+
+    #[test]
+    fn buckets() {
+        for latency in [0, 1, 63, 64, 65, 100, 1000, 123_456, u64::MAX / 3, u64::MAX] {
+            let i = bucket(latency);
+            assert!(i < NUM_BUCKETS);
+            let floor = bucket_floor(i);
+            assert!(floor <= latency && latency - floor <= latency / 32, "{}", latency);
+            assert_eq!(bucket(floor), i);
+        }
+        assert_eq!(bucket(u64::MAX), NUM_BUCKETS - 1);
+    }
+
+    #[test]
+    fn samples_reset_each_interval() {
+        let recorder = IntervalRecorder::new(true);
+        for latency in 1..=100 {
+            recorder.record_sent();
+            recorder.record_received(latency);
+        }
+        recorder.record_error();
+        let sample = recorder.take_sample(Duration::from_secs(1));
+        assert_eq!((sample.sent, sample.received, sample.errors), (100, 100, 1));
+        assert_eq!(sample.p50, Some(bucket_floor(bucket(51))));
+        assert_eq!(sample.p99, Some(bucket_floor(bucket(100))));
+
+        let sample = recorder.take_sample(Duration::from_secs(2));
+        assert_eq!((sample.sent, sample.received, sample.errors), (0, 0, 0));
+        assert_eq!((sample.p50, sample.p99), (None, None));
+
+        let disabled = IntervalRecorder::new(false);
+        disabled.record_received(10);
+        assert_eq!(disabled.take_sample(Duration::ZERO).p50, None);
+    }
+}
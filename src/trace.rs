@@ -0,0 +1,216 @@
+//! Request schedules that the open loop client can record and replay.
+
+use crate::{
+    app::{Work, WorkParseErr},
+    serialize::MAX_REQUEST_PAYLOAD,
+};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    num::ParseIntError,
+    path::Path,
+    time::Duration,
+};
+
+/// One request of a [`Trace`].
+///
+/// Implements [`FromStr`](std::str::FromStr). String format is `[arrival_us] [work]` or
+/// `[arrival_us] [work] [payload_bytes]`, separated by whitespace, where `work` uses the
+/// [`Work`] format and `payload_bytes` is at most [`MAX_REQUEST_PAYLOAD`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceEntry {
+    /// When to send the request, relative to the start of the run.
+    pub arrival: Duration,
+    pub work: Work,
+    /// Bytes of padding to send with the request.
+    pub payload: Option<usize>,
+}
+
+impl std::str::FromStr for TraceEntry {
+    type Err = TraceParseErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sp: Vec<_> = s.split_whitespace().collect();
+        let (arrival, work, payload) = match &sp[..] {
+            [arrival, work] => (arrival, work, None),
+            [arrival, work, payload] => {
+                let payload: usize = payload.parse()?;
+                if payload > MAX_REQUEST_PAYLOAD {
+                    return Err(TraceParseErr::PayloadTooLarge(payload));
+                }
+                (arrival, work, Some(payload))
+            }
+            _ => return Err(TraceParseErr::UnknownFmt(s.to_owned())),
+        };
+        Ok(Self {
+            arrival: Duration::from_micros(arrival.parse()?),
+            work: work.parse()?,
+            payload,
+        })
+    }
+}
+
+impl std::fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.arrival.as_micros(), self.work)?;
+        if let Some(payload) = self.payload {
+            write!(f, " {}", payload)?;
+        }
+        Ok(())
+    }
+}
+
+/// Things that can go wrong when parsing a [`TraceEntry`].
+#[derive(Debug)]
+pub enum TraceParseErr {
+    /// The `arrival_us work [payload_bytes]` format wasn't followed.
+    UnknownFmt(String),
+    /// The arrival time or payload size wasn't a `u64`.
+    U64Parse(ParseIntError),
+    /// The work spec didn't parse.
+    Work(WorkParseErr),
+    /// The payload wouldn't fit in a request message.
+    PayloadTooLarge(usize),
+}
+
+impl From<ParseIntError> for TraceParseErr {
+    fn from(value: ParseIntError) -> Self {
+        Self::U64Parse(value)
+    }
+}
+
+impl From<WorkParseErr> for TraceParseErr {
+    fn from(value: WorkParseErr) -> Self {
+        Self::Work(value)
+    }
+}
+
+impl std::fmt::Display for TraceParseErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownFmt(s) => write!(
+                f,
+                "Unknown trace line {}. Format is [arrival_us] [work] [payload_bytes].",
+                s
+            ),
+            Self::U64Parse(n) => write!(f, "Could not parse trace field {} as u64.", n),
+            Self::Work(e) => write!(f, "{}", e),
+            Self::PayloadTooLarge(n) => write!(
+                f,
+                "Trace payload of {} bytes is larger than the maximum of {}.",
+                n, MAX_REQUEST_PAYLOAD
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TraceParseErr {}
+
+/// A schedule of requests, sorted by arrival time.
+///
+/// Trace files hold one [`TraceEntry`] per line. Blank lines and lines starting with `#` are
+/// ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace(Vec<TraceEntry>);
+
+impl Trace {
+    pub fn new(mut entries: Vec<TraceEntry>) -> Self {
+        entries.sort_by_key(|e| e.arrival);
+        Self(entries)
+    }
+
+    pub fn entries(&self) -> &[TraceEntry] {
+        &self.0
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let mut entries = Vec::new();
+        for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = line
+                .parse()
+                .map_err(|e| anyhow::anyhow!("{}:{}: {}", path.display(), i + 1, e))?;
+            entries.push(entry);
+        }
+        Ok(Self::new(entries))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "# arrival_us work [payload_bytes]")?;
+        for entry in &self.0 {
+            writeln!(out, "{}", entry)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// The entries arriving before `runtime`, dealt round robin over `n` connections.
+    pub fn split(&self, runtime: Duration, n: usize) -> Vec<Vec<TraceEntry>> {
+        let mut split = vec![Vec::new(); n];
+        for (i, entry) in self
+            .0
+            .iter()
+            .take_while(|e| e.arrival < runtime)
+            .enumerate()
+        {
+            split[i % n].push(*entry);
+        }
+        split
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::{Trace, TraceEntry, TraceParseErr};
+    use crate::app::Work;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_trace_entry() {
+        let entry: TraceEntry = "1500 bimodal:10,1000,0.01".parse().expect("parse entry");
+        assert_eq!(entry.arrival, Duration::from_micros(1500));
+        assert!(matches!(entry.work, Work::Bimodal { .. }));
+        assert_eq!(entry.payload, None);
+        assert_eq!(entry.to_string(), "1500 bimodal:10,1000,0.01");
+
+        let entry: TraceEntry = "0  const:5\t32".parse().expect("parse entry with payload");
+        assert_eq!(entry.payload, Some(32));
+        assert_eq!(entry.to_string(), "0 const:5 32");
+
+        assert!(matches!(
+            "10".parse::<TraceEntry>(),
+            Err(TraceParseErr::UnknownFmt(_))
+        ));
+        assert!(matches!(
+            "x imm".parse::<TraceEntry>(),
+            Err(TraceParseErr::U64Parse(_))
+        ));
+        assert!(matches!(
+            "10 foo".parse::<TraceEntry>(),
+            Err(TraceParseErr::Work(_))
+        ));
+        assert!(matches!(
+            "10 imm 4096".parse::<TraceEntry>(),
+            Err(TraceParseErr::PayloadTooLarge(4096))
+        ));
+    }
+
+    #[test]
+    fn split_trace() {
+        let trace = Trace::new(
+            ["300 imm", "0 imm", "100 imm", "200 imm"]
+                .iter()
+                .map(|s| s.parse().unwrap())
+                .collect(),
+        );
+        let split = trace.split(Duration::from_micros(300), 2);
+        let arrivals: Vec<Vec<_>> = split
+            .iter()
+            .map(|c| c.iter().map(|e| e.arrival.as_micros()).collect())
+            .collect();
+        assert_eq!(arrivals, vec![vec![0, 200], vec![100]]);
+    }
+}